@@ -0,0 +1,391 @@
+//! Content-addressed cache of executables uploaded by the host.
+//!
+//! Entries live at `<agent dir>/bin/<hash>/<file name>`, next to the installed agent, so a binary
+//! keeps its original file name and an unchanged binary is found again under the same hash on
+//! later runs. Entries, the files mirrored from the host under `<agent dir>/fs`, and agents that
+//! other versions of cargo-xrun installed next to this one are removed once they have not been
+//! used for [`MAX_AGE`].
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::{Duration, SystemTime},
+};
+
+/// How long cache entries, mirrored files and agents are kept without being used.
+const MAX_AGE: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+/// How often the agent looks for unused files, as recorded by the `gc-stamp` file.
+const GC_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Per-user cache directory of cargo-xrun on this machine, where agents are installed unless the
+/// host picks another directory.
+fn cache_dir() -> io::Result<PathBuf> {
+    #[cfg(windows)]
+    let base = std::env::var_os("LOCALAPPDATA").map(PathBuf::from);
+    #[cfg(not(windows))]
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")));
+
+    base.map(|base| base.join("cargo-xrun"))
+        .ok_or_else(|| io::Error::other("Could not determine the cache directory"))
+}
+
+/// Directory of the installed agent, which holds everything else the agent stores.
+pub(crate) fn agent_dir() -> io::Result<PathBuf> {
+    let exe = std::env::current_exe()?;
+    exe.parent()
+        .map(PathBuf::from)
+        .ok_or_else(|| io::Error::other("Could not determine the agent directory"))
+}
+
+/// Marks the file at `path` as used now, so that garbage collection leaves it alone. Works on
+/// executables that are running, which neither Linux nor Windows lets anyone open for writing.
+fn touch(path: &Path) -> io::Result<()> {
+    let mut options = fs::File::options();
+    options.read(true);
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt as _;
+        // FILE_WRITE_ATTRIBUTES
+        options.access_mode(0x100);
+    }
+    options.open(path)?.set_modified(SystemTime::now())
+}
+
+/// Marks the running agent as used. Other versions of cargo-xrun sharing the directory remove
+/// agents that have not been used for [`MAX_AGE`].
+pub fn mark_used() {
+    if let Ok(exe) = std::env::current_exe() {
+        let _ = touch(&exe);
+    }
+}
+
+fn entry_path(hash: &str, file_name: &str) -> io::Result<PathBuf> {
+    let is_plain_name =
+        |s: &str| !s.is_empty() && s != "." && s != ".." && !s.contains(['/', '\\', ':']);
+    if !is_plain_name(hash) || !is_plain_name(file_name) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid cache entry: {}/{}", hash, file_name),
+        ));
+    }
+    Ok(agent_dir()?.join("bin").join(hash).join(file_name))
+}
+
+/// `cache-lookup <hash> <file name>`: prints the entry path. Exits with 0 if the entry exists and
+/// with 2 if it still has to be uploaded.
+pub fn lookup(args: &[String]) -> ExitCode {
+    let [hash, file_name] = args else {
+        eprintln!("cargo-xrun-remote: usage: cache-lookup <hash> <file name>");
+        return ExitCode::from(1);
+    };
+    match entry_path(hash, file_name) {
+        Ok(path) => {
+            println!("{}", path.display());
+            if path.is_file() {
+                let _ = touch(&path);
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::from(2)
+            }
+        }
+        Err(err) => {
+            eprintln!("cargo-xrun-remote: {}", err);
+            ExitCode::from(1)
+        }
+    }
+}
+
+/// `install-self <hash> [dir]`: copies the running agent to `<dir>/agent-<hash>`, by default in
+/// the cache directory, and prints the path of the copy, so later sessions can call it without
+/// going through the share. Other agents in that directory are left to garbage collection, since
+/// other versions of cargo-xrun may still use them.
+pub fn install_self(args: &[String]) -> ExitCode {
+    let (hash, dir) = match args {
+        [hash] => (hash, None),
+        [hash, dir] => (hash, Some(PathBuf::from(dir))),
+        _ => {
+            eprintln!("cargo-xrun-remote: usage: install-self <hash> [dir]");
+            return ExitCode::from(1);
+        }
+    };
+    match install_self_at(hash, dir) {
+        Ok(path) => {
+            println!("{}", path.display());
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("cargo-xrun-remote: Failed to install agent: {}", err);
+            ExitCode::from(1)
+        }
+    }
+}
+
+fn install_self_at(hash: &str, dir: Option<PathBuf>) -> io::Result<PathBuf> {
+    let file_name = format!("agent-{}{}", hash, std::env::consts::EXE_SUFFIX);
+    let dir = match dir {
+        Some(dir) => std::path::absolute(dir)?,
+        None => cache_dir()?,
+    };
+    let path = dir.join(&file_name);
+    fs::create_dir_all(&dir)?;
+
+    let partial_path = dir.join(format!("{}.part-{}", file_name, std::process::id()));
+    fs::copy(std::env::current_exe()?, &partial_path)?;
+    if let Err(err) = fs::rename(&partial_path, &path) {
+        let _ = fs::remove_file(&partial_path);
+        // Same as in `put_entry`: an agent with this hash that is already there is identical.
+        if !path.is_file() {
+            return Err(err);
+        }
+    }
+
+    Ok(path)
+}
+
+/// Removes cache entries, mirrored files, tokens of sessions the host never closed and other
+/// agents that have not been used for [`MAX_AGE`]. Only looks once every [`GC_INTERVAL`], and
+/// never fails: whatever cannot be removed now is tried again later.
+pub fn collect_garbage() {
+    let Ok(dir) = agent_dir() else {
+        return;
+    };
+    let stamp = dir.join("gc-stamp");
+    let now = SystemTime::now();
+    let last_run = fs::metadata(&stamp).and_then(|metadata| metadata.modified());
+    if last_run.is_ok_and(|last_run| now.duration_since(last_run).unwrap_or_default() < GC_INTERVAL)
+    {
+        return;
+    }
+    if fs::write(&stamp, "").is_err() {
+        return;
+    }
+    let cutoff = now - MAX_AGE;
+    remove_unused(&dir.join("bin"), cutoff);
+    remove_unused(&dir.join("fs"), cutoff);
+    remove_unused(&dir.join("sessions"), cutoff);
+    if let Some(current) = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.file_name().map(|name| name.to_os_string()))
+    {
+        remove_unused_agents(&dir, &current, cutoff);
+    }
+}
+
+/// Removes the agents in `dir` other than `current` last used before `cutoff`. Each agent marks
+/// itself as used whenever it runs, so this only catches those no cargo-xrun has run for a while.
+fn remove_unused_agents(dir: &Path, current: &std::ffi::OsStr, cutoff: SystemTime) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let file_name = entry.file_name();
+        if file_name == current || !file_name.to_string_lossy().starts_with("agent-") {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let last_used = [metadata.modified(), metadata.accessed()]
+            .into_iter()
+            .flatten()
+            .max();
+        if metadata.is_file() && last_used.is_some_and(|last_used| last_used < cutoff) {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+/// Removes the files under `dir` last modified or read before `cutoff`, and the directories this
+/// leaves empty.
+fn remove_unused(dir: &Path, cutoff: SystemTime) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if metadata.is_dir() {
+            remove_unused(&entry.path(), cutoff);
+            // Only succeeds once the directory is empty.
+            let _ = fs::remove_dir(entry.path());
+            continue;
+        }
+        let last_used = [metadata.modified(), metadata.accessed()]
+            .into_iter()
+            .flatten()
+            .max();
+        if last_used.is_some_and(|last_used| last_used < cutoff) {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+/// `cache-put <hash> <file name> <size>`: stores stdin as the entry and prints its path.
+pub fn put(args: &[String]) -> ExitCode {
+    let [hash, file_name, size] = args else {
+        eprintln!("cargo-xrun-remote: usage: cache-put <hash> <file name> <size>");
+        return ExitCode::from(1);
+    };
+    let Ok(size) = size.parse::<u64>() else {
+        eprintln!("cargo-xrun-remote: invalid size: {}", size);
+        return ExitCode::from(1);
+    };
+    match put_entry(hash, file_name, size) {
+        Ok(path) => {
+            println!("{}", path.display());
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("cargo-xrun-remote: Failed to store {}: {}", file_name, err);
+            ExitCode::from(1)
+        }
+    }
+}
+
+fn put_entry(hash: &str, file_name: &str, size: u64) -> io::Result<PathBuf> {
+    collect_garbage();
+    let path = entry_path(hash, file_name)?;
+    let dir = path.parent().unwrap();
+    fs::create_dir_all(dir)?;
+
+    // Write next to the entry and rename, so concurrent uploads of the same binary never expose a
+    // partially written file.
+    let partial_path = dir.join(format!("{}.part-{}", file_name, std::process::id()));
+    let mut file = fs::File::create(&partial_path)?;
+    let written = io::copy(&mut io::stdin().lock(), &mut file)?;
+    if written != size {
+        drop(file);
+        let _ = fs::remove_file(&partial_path);
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("received {} of {} bytes", written, size),
+        ));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o755))?;
+    }
+    drop(file);
+    if let Err(err) = fs::rename(&partial_path, &path) {
+        let _ = fs::remove_file(&partial_path);
+        // Windows refuses to replace an executable that is running. Whoever put it there stored
+        // the same content, so that copy is as good as ours.
+        if !path.is_file() {
+            return Err(err);
+        }
+    }
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_entry_path() {
+        let path = entry_path("0123abcd", "app").unwrap();
+        assert!(path.ends_with("bin/0123abcd/app"));
+        assert!(entry_path("..", "app").is_err());
+        assert!(entry_path("0123abcd", "../agent").is_err());
+        assert!(entry_path("0123abcd", "").is_err());
+    }
+
+    #[test]
+    fn test_install_self_at() {
+        let dir = tempfile::tempdir().unwrap();
+        let exe_suffix = std::env::consts::EXE_SUFFIX;
+        fs::write(dir.path().join(format!("agent-old{}", exe_suffix)), "old").unwrap();
+        fs::write(dir.path().join("agent-new.part-1"), "partial").unwrap();
+        fs::write(dir.path().join("unrelated"), "").unwrap();
+
+        let path = install_self_at("new", Some(dir.path().to_path_buf())).unwrap();
+        assert_eq!(path, dir.path().join(format!("agent-new{}", exe_suffix)));
+        assert_eq!(
+            fs::read(&path).unwrap(),
+            fs::read(std::env::current_exe().unwrap()).unwrap()
+        );
+        // Other agents may belong to other versions of cargo-xrun that are still in use.
+        assert_eq!(
+            file_names(dir.path()),
+            [
+                format!("agent-new{}", exe_suffix),
+                "agent-new.part-1".to_string(),
+                format!("agent-old{}", exe_suffix),
+                "unrelated".to_string()
+            ]
+        );
+
+        // Installing the current agent again keeps it.
+        install_self_at("new", Some(dir.path().to_path_buf())).unwrap();
+        assert!(path.is_file());
+    }
+
+    fn set_last_used(path: &Path, time: SystemTime) {
+        let times = fs::FileTimes::new().set_accessed(time).set_modified(time);
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_times(times)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_remove_unused() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = SystemTime::now();
+        for name in ["old/app", "recent/app", "mixed/old", "mixed/recent"] {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, "").unwrap();
+        }
+        let long_ago = now - MAX_AGE - Duration::from_secs(60);
+        set_last_used(&dir.path().join("old/app"), long_ago);
+        set_last_used(&dir.path().join("mixed/old"), long_ago);
+
+        remove_unused(dir.path(), now - MAX_AGE);
+        assert_eq!(file_names(dir.path()), ["mixed", "recent"]);
+        assert_eq!(file_names(&dir.path().join("mixed")), ["recent"]);
+    }
+
+    #[test]
+    fn test_remove_unused_agents() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = SystemTime::now();
+        let long_ago = now - MAX_AGE - Duration::from_secs(60);
+        for name in ["agent-current", "agent-old", "agent-recent", "unrelated"] {
+            let path = dir.path().join(name);
+            fs::write(&path, "").unwrap();
+            if name != "agent-recent" {
+                set_last_used(&path, long_ago);
+            }
+        }
+
+        remove_unused_agents(dir.path(), "agent-current".as_ref(), now - MAX_AGE);
+        assert_eq!(
+            file_names(dir.path()),
+            ["agent-current", "agent-recent", "unrelated"]
+        );
+
+        // Touching a file marks it as used.
+        let path = dir.path().join("agent-current");
+        touch(&path).unwrap();
+        remove_unused_agents(dir.path(), "agent-other".as_ref(), now - MAX_AGE);
+        assert!(path.is_file());
+    }
+}
@@ -0,0 +1,49 @@
+//! Self-check for `cargo xrun doctor`.
+
+use std::{
+    io::{self, Read as _},
+    process::ExitCode,
+};
+
+use crate::http;
+
+/// Architecture of the machine, as opposed to the one the agent was built for. A 32-bit agent on
+/// 64-bit Windows reports the 64-bit architecture.
+fn machine_arch() -> Option<String> {
+    #[cfg(windows)]
+    {
+        std::env::var("PROCESSOR_ARCHITEW6432")
+            .or_else(|_| std::env::var("PROCESSOR_ARCHITECTURE"))
+            .ok()
+    }
+    #[cfg(unix)]
+    {
+        let mut name: libc::utsname = unsafe { std::mem::zeroed() };
+        if unsafe { libc::uname(&mut name) } != 0 {
+            return None;
+        }
+        let machine = unsafe { std::ffi::CStr::from_ptr(name.machine.as_ptr()) };
+        Some(machine.to_string_lossy().into_owned())
+    }
+}
+
+/// `doctor`: prints `arch <machine architecture>`, then PUTs `ping` to the URL on the host's file
+/// server read from stdin, which carries the session token, through the forwarded port and prints
+/// `tunnel ok` or `tunnel <error>`.
+pub fn run(args: &[String]) -> ExitCode {
+    let mut url = String::new();
+    if !args.is_empty() || io::stdin().lock().read_to_string(&mut url).is_err() {
+        eprintln!("cargo-xrun-remote: usage: doctor < <url>");
+        return ExitCode::from(1);
+    }
+    let url = url.trim();
+    println!(
+        "arch {}",
+        machine_arch().as_deref().unwrap_or(std::env::consts::ARCH)
+    );
+    match http::put(url, b"ping") {
+        Ok(()) => println!("tunnel ok"),
+        Err(err) => println!("tunnel {}", err),
+    }
+    ExitCode::SUCCESS
+}
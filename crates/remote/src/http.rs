@@ -0,0 +1,90 @@
+//! Minimal HTTP/1.0 client for talking to the host's file server, either through the forwarded
+//! port on the remote or directly on the host.
+//!
+//! HTTP/1.0 keeps the server from using chunked encoding, so a response body simply runs until
+//! the connection closes.
+
+use std::{
+    io::{self, BufRead as _, BufReader, Read as _, Write},
+    net::TcpStream,
+};
+
+/// Downloads `url` (`http://host:port/path`) into `out`, returning the body size.
+pub fn get(url: &str, out: &mut impl Write) -> io::Result<u64> {
+    let mut reader = request("GET", url, &[])?;
+    io::copy(&mut reader, out)
+}
+
+/// Uploads `body` to `url` (`http://host:port/path`).
+pub fn put(url: &str, body: &[u8]) -> io::Result<()> {
+    request("PUT", url, body)?;
+    Ok(())
+}
+
+/// Sends a request and returns the response body, failing unless the status is 200.
+fn request(method: &str, url: &str, body: &[u8]) -> io::Result<BufReader<TcpStream>> {
+    let (authority, path) = url
+        .strip_prefix("http://")
+        .map(|rest| rest.split_at(rest.find('/').unwrap_or(rest.len())))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unsupported URL: {}", url),
+            )
+        })?;
+
+    let mut stream = TcpStream::connect(authority)?;
+    write!(
+        stream,
+        "{} {} HTTP/1.0\r\nHost: {}\r\nContent-Length: {}\r\n\r\n",
+        method,
+        percent_encode_path(path),
+        authority,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+
+    // Skip the headers; the body ends when the server closes the connection.
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line == "\r\n" || line == "\n" {
+            break;
+        }
+    }
+
+    let status = status_line.split_whitespace().nth(1);
+    if status != Some("200") {
+        // The server may explain the failure in the body.
+        let mut reason = String::new();
+        let _ = reader.take(1024).read_to_string(&mut reason);
+        let reason = reason.trim();
+        return Err(io::Error::other(format!(
+            "{} {} failed: {}{}{}",
+            method,
+            path,
+            status_line.trim(),
+            if reason.is_empty() { "" } else { ": " },
+            reason
+        )));
+    }
+
+    Ok(reader)
+}
+
+/// Percent-encodes everything in `path` except unreserved characters and `/`.
+fn percent_encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for &byte in path.as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
@@ -1,9 +1,24 @@
+#[cfg(feature = "decode")]
+mod cache;
+#[cfg(feature = "decode")]
+mod doctor;
+pub mod http;
+pub mod outcome;
+pub mod protocol;
+#[cfg(feature = "decode")]
+mod session;
+
 #[cfg(feature = "decode")]
 use wincode::SchemaRead;
 #[cfg(feature = "encode")]
 use wincode::SchemaWrite;
 
 /// Execution context passed from host to remote binary.
+///
+/// Paths under `webdav_path` name host files. Windows agents reach them through the WebDAV share
+/// as they are. Linux agents see a snapshot instead, taken as the run starts: the working
+/// directory, the `CARGO_MANIFEST_DIR` directory and the files named by `bin_path`, `args` and
+/// `stdin` are mirrored to the remote, and changes the host makes later are not seen.
 #[cfg_attr(feature = "encode", derive(SchemaWrite))]
 #[cfg_attr(feature = "decode", derive(SchemaRead))]
 #[derive(Debug, Clone)]
@@ -13,27 +28,89 @@ pub struct ExecContext {
     pub bin_path: String,
     pub args: Vec<String>,
     pub webdav_path: String,
+    /// Id of the session opened with `session-open`. `webdav_path` and `report_url` carry it where
+    /// the file server expects the session's token, which the agent puts in their place.
+    pub session: String,
+    /// Start the executable with `envs` only instead of inheriting the agent's environment.
+    pub clear_env: bool,
+    /// URL the agent PUTs the encoded [`outcome::Outcome`] to once the executable has ended.
+    pub report_url: String,
+    /// `argv[0]` for the executable instead of `bin_path`. Unix only: Windows derives it from the
+    /// executable path.
+    pub argv0: Option<String>,
+    /// Variables to remove from the environment the executable inherits, applied after `envs`.
+    pub env_remove: Vec<String>,
+    /// File mode creation mask for the executable, such as `0o022`. Unix only.
+    pub umask: Option<u32>,
+    pub stdin: StdinMode,
+    /// Kill the executable after this many seconds and report [`outcome::Outcome::TimedOut`].
+    pub timeout_secs: Option<u64>,
+}
+
+/// Where the executable reads its standard input from.
+#[cfg_attr(feature = "encode", derive(SchemaWrite))]
+#[cfg_attr(feature = "decode", derive(SchemaRead))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum StdinMode {
+    /// The agent's, which is the ssh session's.
+    #[default]
+    Inherit,
+    Null,
+    /// This file, as a path under `webdav_path`.
+    File(String),
+}
+
+impl ExecContext {
+    /// Agent capabilities needed to run this context as asked.
+    pub fn required_capabilities(&self) -> u32 {
+        let mut capabilities = 0;
+        if !self.report_url.is_empty() {
+            capabilities |= protocol::CAP_OUTCOME;
+        }
+        if self.clear_env {
+            capabilities |= protocol::CAP_CLEAR_ENV;
+        }
+        if self.argv0.is_some()
+            || !self.env_remove.is_empty()
+            || self.umask.is_some()
+            || self.stdin != StdinMode::Inherit
+            || self.timeout_secs.is_some()
+        {
+            capabilities |= protocol::CAP_LAUNCH_OPTIONS;
+        }
+        capabilities
+    }
 }
 
 #[cfg(feature = "encode")]
 pub mod encode {
-    use super::ExecContext;
+    use super::{ExecContext, protocol};
     use base64::engine::{Engine, general_purpose::URL_SAFE_NO_PAD};
 
+    /// Encodes `ctx` behind a [`protocol::Header`] with the capabilities it requires.
     pub fn encode_context(ctx: &ExecContext) -> String {
-        let bytes = wincode::serialize(ctx).unwrap();
+        let header = protocol::Header {
+            version: protocol::VERSION,
+            capabilities: ctx.required_capabilities(),
+        };
+        let mut bytes = header.to_bytes().to_vec();
+        bytes.extend(wincode::serialize(ctx).unwrap());
         URL_SAFE_NO_PAD.encode(&bytes)
     }
 }
 
 #[cfg(feature = "decode")]
 pub mod decode {
-    use super::ExecContext;
+    use super::{ExecContext, protocol};
     use base64::engine::{Engine, general_purpose::URL_SAFE_NO_PAD};
 
+    /// Decodes a context, refusing it with a [`protocol::ProtocolError`] unless this agent speaks
+    /// the host's protocol version and has the capabilities the context requires.
     pub fn decode_context(encoded: &str) -> Result<ExecContext, Box<dyn std::error::Error>> {
         let bytes = URL_SAFE_NO_PAD.decode(encoded)?;
-        let ctx = wincode::deserialize(&bytes)?;
+        let (header, bytes) = protocol::Header::split(&bytes)?;
+        protocol::Header::CURRENT.serves(header)?;
+        let ctx = wincode::deserialize(bytes)?;
         Ok(ctx)
     }
 }
@@ -44,52 +121,54 @@ struct WebDavMount {
     webdav_prefix: String,
 }
 
+#[cfg(all(feature = "decode", windows))]
+#[link(name = "kernel32")]
+unsafe extern "system" {
+    fn DefineDosDeviceW(flags: u32, device_name: *const u16, target_path: *const u16) -> i32;
+    fn QueryDosDeviceW(device_name: *const u16, target_path: *mut u16, max: u32) -> u32;
+}
+
+#[cfg(all(feature = "decode", windows))]
+fn to_wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain([0]).collect()
+}
+
 #[cfg(all(feature = "decode", windows))]
 impl WebDavMount {
-    fn mount(webdav_path: &str) -> Result<Self, std::io::Error> {
-        use std::process::Command;
+    /// Maps a free drive letter to `share`, as `subst` would, and rewrites paths under
+    /// `webdav_path` onto it. The mapping is made directly rather than by running `subst`, whose
+    /// command line would show the session token in `share`.
+    fn mount(share: &str, webdav_path: &str) -> Result<Self, std::io::Error> {
+        const ERROR_FILE_NOT_FOUND: i32 = 2;
 
+        let share = to_wide(share);
         // Try drive letters from Z: down to A:
         let mut last_error = None;
         for letter in (b'A'..=b'Z').rev() {
             let drive_letter = format!("{}:", letter as char);
+            let device = to_wide(&drive_letter);
 
-            // Try to create drive mapping with subst
-            let output = Command::new("subst")
-                .args(&[&drive_letter, webdav_path])
-                .output()?;
+            // Skip letters that name a drive or an existing mapping.
+            let mut target = [0u16; 1024];
+            let found = unsafe {
+                QueryDosDeviceW(device.as_ptr(), target.as_mut_ptr(), target.len() as u32)
+            };
+            if found != 0
+                || std::io::Error::last_os_error().raw_os_error() != Some(ERROR_FILE_NOT_FOUND)
+            {
+                continue;
+            }
 
-            if output.status.success() {
+            if unsafe { DefineDosDeviceW(0, device.as_ptr(), share.as_ptr()) } != 0 {
                 return Ok(WebDavMount {
                     drive_letter,
                     webdav_prefix: webdav_path.to_string(),
                 });
             }
-
-            // Subst failed - check if it's a drive-in-use error or something else
-            let check_output = Command::new("subst").output()?;
-            if check_output.status.success() {
-                let stdout = String::from_utf8_lossy(&check_output.stdout);
-                // If this drive letter appears in "subst" output, it's already mapped
-                if stdout.contains(&drive_letter) {
-                    // Drive letter in use, try next one
-                    continue;
-                }
-            }
-
-            // Drive isn't in subst list, so the error is something else (e.g., path not found)
-            // Save this error and try next letter, but if all fail, return this error
-            if last_error.is_none() {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                let stdout = String::from_utf8_lossy(&output.stdout);
-                last_error = Some(format!("{}{}", stderr, stdout).trim().to_string());
-            }
+            last_error = Some(std::io::Error::last_os_error());
         }
 
-        Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            last_error.unwrap_or_else(|| "No available drive letters".to_string())
-        ))
+        Err(last_error.unwrap_or_else(|| std::io::Error::other("No available drive letters")))
     }
 
     fn transform_path(&self, path: &str) -> String {
@@ -104,12 +183,222 @@ impl WebDavMount {
 #[cfg(all(feature = "decode", windows))]
 impl Drop for WebDavMount {
     fn drop(&mut self) {
-        use std::process::Command;
+        const DDD_REMOVE_DEFINITION: u32 = 2;
 
-        // Best effort unmount using subst /d - ignore errors
-        let _ = Command::new("subst")
-            .args(&[&self.drive_letter, "/d"])
-            .output();
+        // Best effort unmount, as `subst /d` - ignore errors
+        let device = to_wide(&self.drive_letter);
+        unsafe { DefineDosDeviceW(DDD_REMOVE_DEFINITION, device.as_ptr(), std::ptr::null()) };
+    }
+}
+
+/// A line of the file server's `/tree` listing: `<kind> <size> <modified> <path>`.
+#[cfg(all(feature = "decode", unix))]
+struct TreeEntry<'a> {
+    /// `d` for directories, `f` for files, `x` for executables and `s` for directories left out.
+    kind: &'a str,
+    size: u64,
+    modified: std::time::SystemTime,
+    /// Relative to the listed directory, and empty for a listed file.
+    path: &'a str,
+}
+
+#[cfg(all(feature = "decode", unix))]
+impl<'a> TreeEntry<'a> {
+    fn parse(line: &'a str) -> Result<Self, std::io::Error> {
+        let mut fields = line.splitn(4, ' ');
+        let (Some(kind), Some(Ok(size)), Some(Ok(modified)), Some(path)) = (
+            fields.next(),
+            fields.next().map(str::parse),
+            fields.next().map(str::parse),
+            fields.next(),
+        ) else {
+            return Err(std::io::Error::other(format!(
+                "Malformed listing entry: {}",
+                line
+            )));
+        };
+        Ok(TreeEntry {
+            kind,
+            size,
+            modified: std::time::UNIX_EPOCH + std::time::Duration::from_nanos(modified),
+            path,
+        })
+    }
+
+    /// Whether the file at `local_path` is the copy of this entry from an earlier run.
+    fn is_mirrored_at(&self, local_path: &std::path::Path) -> bool {
+        std::fs::metadata(local_path).is_ok_and(|metadata| {
+            metadata.len() == self.size
+                && metadata
+                    .modified()
+                    .is_ok_and(|modified| modified == self.modified)
+        })
+    }
+}
+
+/// Local stand-in for the WebDAV share on hosts without a WebDAV client.
+///
+/// Paths under the WebDAV root are mapped onto a mirror next to the installed agent. Only what
+/// is fetched over HTTP exists there: the executable, path arguments, the stdin file, and the
+/// directories the executable starts in, as synced with [`HttpMirror::sync_dir`]. Each run
+/// checks these against the host and only downloads what changed since the previous run.
+#[cfg(all(feature = "decode", unix))]
+struct HttpMirror {
+    root: String,
+    webdav_prefix: String,
+    /// `webdav_prefix` with the session token, where files are fetched from.
+    share: String,
+}
+
+#[cfg(all(feature = "decode", unix))]
+impl HttpMirror {
+    fn new(share: &str, webdav_path: &str) -> Result<Self, std::io::Error> {
+        let exe = std::env::current_exe()?;
+        let root = exe
+            .parent()
+            .and_then(|dir| dir.to_str())
+            .ok_or_else(|| std::io::Error::other("Agent directory is not valid UTF-8"))?;
+        Ok(HttpMirror {
+            root: root.to_string(),
+            webdav_prefix: webdav_path.to_string(),
+            share: share.to_string(),
+        })
+    }
+
+    fn transform_path(&self, path: &str) -> String {
+        // e.g., "http://localhost:port/token/fs/home/user" -> "/home/user/.cache/cargo-xrun/fs/home/user"
+        let prefix_with_slash = format!("{}/", self.webdav_prefix);
+        path.replace(&prefix_with_slash, &format!("{}/", self.root))
+    }
+
+    /// Lists `path` with the file server's `/tree`.
+    fn list(&self, path: &str) -> Result<String, std::io::Error> {
+        let host_path = path
+            .strip_prefix(&format!("{}/fs", self.webdav_prefix))
+            .ok_or_else(|| std::io::Error::other(format!("Not a host path: {}", path)))?;
+        let mut listing = Vec::new();
+        http::get(&format!("{}/tree{}", self.share, host_path), &mut listing)?;
+        String::from_utf8(listing).map_err(std::io::Error::other)
+    }
+
+    /// Makes sure the mirror holds the current version of the file at `path`, and returns its
+    /// local path. The file is only downloaded if its size or modification time differ from the
+    /// copy of an earlier run.
+    fn fetch(&self, path: &str, executable: bool) -> Result<String, std::io::Error> {
+        if !path.starts_with(&self.webdav_prefix) {
+            return Ok(path.to_string());
+        }
+        let listing = self.list(path)?;
+        let entry = match listing.lines().collect::<Vec<_>>()[..] {
+            [line] => TreeEntry::parse(line)?,
+            _ => return Err(std::io::Error::other(format!("Not a file: {}", path))),
+        };
+        if !entry.path.is_empty() || !matches!(entry.kind, "f" | "x") {
+            return Err(std::io::Error::other(format!("Not a file: {}", path)));
+        }
+        let local_path = self.transform_path(path);
+        if !entry.is_mirrored_at(std::path::Path::new(&local_path)) {
+            return self.download(path, executable, entry.modified);
+        }
+        if executable {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&local_path, std::fs::Permissions::from_mode(0o755))?;
+        }
+        Ok(local_path)
+    }
+
+    /// Downloads the file at `path` into the mirror, with its modification time set to the host's
+    /// `modified`, and returns its local path.
+    fn download(
+        &self,
+        path: &str,
+        executable: bool,
+        modified: std::time::SystemTime,
+    ) -> Result<String, std::io::Error> {
+        use std::{fs, os::unix::fs::PermissionsExt};
+
+        let local_path = self.transform_path(path);
+        let local_path_ref = std::path::Path::new(&local_path);
+        if let Some(parent) = local_path_ref.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Download next to the destination and rename, so a previous copy that is still running
+        // keeps its inode.
+        let partial_path = format!("{}.part-{}", local_path, std::process::id());
+        let mut file = fs::File::create(&partial_path)?;
+        let url = path.replacen(&self.webdav_prefix, &self.share, 1);
+        if let Err(err) = http::get(&url, &mut file) {
+            drop(file);
+            let _ = fs::remove_file(&partial_path);
+            return Err(err);
+        }
+        let mode = if executable { 0o755 } else { 0o644 };
+        file.set_permissions(fs::Permissions::from_mode(mode))?;
+        file.set_modified(modified)?;
+        drop(file);
+        fs::rename(&partial_path, &local_path)?;
+
+        Ok(local_path)
+    }
+
+    /// Brings the mirror of the host directory at `path` up to date with the host, as listed by
+    /// the file server's `/tree`: files that changed since the last run are downloaded, and those
+    /// gone from the host are removed. Target directories are left out, and so are left alone
+    /// in the mirror. Fails if the directory is too large to mirror.
+    fn sync_dir(&self, path: &str) -> Result<(), std::io::Error> {
+        use std::{collections::HashSet, fs, path::Path};
+
+        if !path.starts_with(&self.webdav_prefix) {
+            return Ok(());
+        }
+        let listing = self.list(path)?;
+        let local_dir = self.transform_path(path);
+        let local_dir = Path::new(&local_dir);
+        fs::create_dir_all(local_dir)?;
+        let mut listed = HashSet::new();
+        let mut skipped = HashSet::new();
+        for line in listing.lines() {
+            let entry = TreeEntry::parse(line)?;
+            let local_path = local_dir.join(entry.path);
+            match entry.kind {
+                "s" => {
+                    skipped.insert(local_path);
+                }
+                "d" => {
+                    fs::create_dir_all(&local_path)?;
+                    listed.insert(local_path);
+                }
+                _ => {
+                    if !entry.is_mirrored_at(&local_path) {
+                        self.download(
+                            &format!("{}/{}", path, entry.path),
+                            entry.kind == "x",
+                            entry.modified,
+                        )?;
+                    }
+                    listed.insert(local_path);
+                }
+            }
+        }
+
+        // Remove what is gone from the host.
+        let mut pending = vec![local_dir.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            for entry in fs::read_dir(&dir)?.flatten() {
+                let path = entry.path();
+                if skipped.contains(&path) || listed.contains(&path) {
+                    if !skipped.contains(&path) && entry.file_type()?.is_dir() {
+                        pending.push(path);
+                    }
+                } else if entry.file_type()?.is_dir() {
+                    fs::remove_dir_all(&path)?;
+                } else {
+                    fs::remove_file(&path)?;
+                }
+            }
+        }
+        Ok(())
     }
 }
 
@@ -118,27 +407,81 @@ pub fn main() -> std::process::ExitCode {
     use std::{env, process::Command};
 
     let args: Vec<String> = env::args().collect();
-    #[cfg(windows)]
-    let mut ctx = decode::decode_context(&args[1]).unwrap();
-    #[cfg(not(windows))]
-    let ctx = decode::decode_context(&args[1]).unwrap();
+    match args.get(1).map(String::as_str) {
+        Some("cache-lookup") => return cache::lookup(&args[2..]),
+        Some("cache-put") => return cache::put(&args[2..]),
+        Some("install-self") => return cache::install_self(&args[2..]),
+        Some("session-open") => return session::open(&args[2..]),
+        Some("session-close") => return session::close(&args[2..]),
+        Some("doctor") => return doctor::run(&args[2..]),
+        Some("version") => {
+            println!("{}", protocol::Header::CURRENT.encode());
+            return std::process::ExitCode::SUCCESS;
+        }
+        _ => {}
+    }
+
+    let Some(encoded) = args.get(1) else {
+        eprintln!("cargo-xrun-remote: usage: <encoded context> | version | <subcommand> ...");
+        return std::process::ExitCode::from(1);
+    };
+    let mut ctx = match decode::decode_context(encoded) {
+        Ok(ctx) => ctx,
+        Err(err) => {
+            eprintln!(
+                "cargo-xrun-remote: Cannot run the context from the host: {}",
+                err
+            );
+            return std::process::ExitCode::from(1);
+        }
+    };
+    cache::collect_garbage();
+    cache::mark_used();
+
+    let token = match session::token(&ctx.session) {
+        Ok(token) => token,
+        Err(err) => {
+            eprintln!(
+                "cargo-xrun-remote: Cannot find the token of session {}, which the host has to \
+                open first: {}",
+                ctx.session, err
+            );
+            return std::process::ExitCode::from(1);
+        }
+    };
+    let share = ctx.webdav_path.replacen(&ctx.session, &token, 1);
+    ctx.report_url = ctx.report_url.replacen(&ctx.session, &token, 1);
 
     // On Windows, mount WebDAV path to drive letter
     #[cfg(windows)]
     let _mount = {
         // Mount the WebDAV root directly - no delay needed since UNC paths work immediately
-        match WebDavMount::mount(&ctx.webdav_path) {
+        match WebDavMount::mount(&share, &ctx.webdav_path) {
             Ok(mount) => {
                 // Transform all paths by replacing ALL occurrences of WebDAV prefix
                 ctx.cwd = mount.transform_path(&ctx.cwd);
                 ctx.bin_path = mount.transform_path(&ctx.bin_path);
+                ctx.args = ctx
+                    .args
+                    .into_iter()
+                    .map(|arg| mount.transform_path(&arg))
+                    .collect();
                 ctx.envs = ctx.envs
                     .into_iter()
                     .map(|(k, v)| (k, mount.transform_path(&v)))
                     .collect();
+                if let StdinMode::File(path) = &mut ctx.stdin {
+                    *path = mount.transform_path(path);
+                }
 
                 // Change to the transformed path (now using drive letter)
-                env::set_current_dir(&ctx.cwd).unwrap();
+                if let Err(e) = env::set_current_dir(&ctx.cwd) {
+                    eprintln!(
+                        "cargo-xrun-remote: Failed to enter working directory {}: {}",
+                        ctx.cwd, e
+                    );
+                    return std::process::ExitCode::from(1);
+                }
 
                 mount
             }
@@ -150,43 +493,221 @@ pub fn main() -> std::process::ExitCode {
         }
     };
 
-    #[cfg(not(windows))]
-    env::set_current_dir(&ctx.cwd).unwrap();
+    // On Unix, fetch the executable over HTTP and map the remaining paths onto the mirror
+    #[cfg(unix)]
+    {
+        let mirror = match HttpMirror::new(&share, &ctx.webdav_path) {
+            Ok(mirror) => mirror,
+            Err(e) => {
+                eprintln!("cargo-xrun-remote: Failed to set up local mirror: {}", e);
+                return std::process::ExitCode::from(1);
+            }
+        };
+        // The working directory and the package directory hold the files programs and tests
+        // read at run time, so mirror them in full. A directory inside another one is covered by
+        // the outer one.
+        let manifest_dir = ctx
+            .envs
+            .iter()
+            .find(|(name, _)| name == "CARGO_MANIFEST_DIR")
+            .map(|(_, dir)| dir.as_str());
+        let mut dirs = [Some(ctx.cwd.as_str()), manifest_dir]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        dirs.sort_by_key(|dir| dir.len());
+        for (i, dir) in dirs.iter().enumerate() {
+            let is_inside = |outer: &&str| {
+                dir.strip_prefix(outer)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            };
+            if dirs[..i].iter().any(is_inside) {
+                continue;
+            }
+            if let Err(e) = mirror.sync_dir(dir) {
+                eprintln!(
+                    "cargo-xrun-remote: Failed to mirror {} from host: {}",
+                    dir.strip_prefix(&format!("{}/fs", mirror.webdav_prefix))
+                        .unwrap_or(dir),
+                    e
+                );
+                return std::process::ExitCode::from(1);
+            }
+        }
+        ctx.bin_path = match mirror.fetch(&ctx.bin_path, true) {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!(
+                    "cargo-xrun-remote: Failed to fetch {} from host: {}",
+                    ctx.bin_path, e
+                );
+                return std::process::ExitCode::from(1);
+            }
+        };
+        // Path arguments may name input files, so fetch what exists on the host. The rest, such as
+        // directories or files yet to be written, only get their parent directory.
+        for arg in &mut ctx.args {
+            let Some(start) = arg.find(&mirror.webdav_prefix) else {
+                continue;
+            };
+            let local_path = mirror.fetch(&arg[start..], false).unwrap_or_else(|_| {
+                let local_path = mirror.transform_path(&arg[start..]);
+                if let Some(parent) = std::path::Path::new(&local_path).parent() {
+                    let _ = std::fs::create_dir_all(parent);
+                }
+                local_path
+            });
+            arg.replace_range(start.., &local_path);
+        }
+        ctx.cwd = mirror.transform_path(&ctx.cwd);
+        ctx.envs = ctx
+            .envs
+            .into_iter()
+            .map(|(k, v)| (k, mirror.transform_path(&v)))
+            .collect();
+        if let StdinMode::File(path) = &mut ctx.stdin {
+            *path = match mirror.fetch(path, false) {
+                Ok(path) => path,
+                Err(e) => {
+                    eprintln!(
+                        "cargo-xrun-remote: Failed to fetch stdin file {} from host: {}",
+                        path, e
+                    );
+                    return std::process::ExitCode::from(1);
+                }
+            };
+        }
+
+        if let Err(e) =
+            std::fs::create_dir_all(&ctx.cwd).and_then(|()| env::set_current_dir(&ctx.cwd))
+        {
+            eprintln!(
+                "cargo-xrun-remote: Failed to enter working directory {}: {}",
+                ctx.cwd, e
+            );
+            return std::process::ExitCode::from(1);
+        }
+    }
 
     let mut cmd = Command::new(&ctx.bin_path);
+    if ctx.clear_env {
+        cmd.env_clear();
+        // Windows processes cannot even load system DLLs without SystemRoot.
+        #[cfg(windows)]
+        if let Some(system_root) = env::var_os("SystemRoot") {
+            cmd.env("SystemRoot", system_root);
+        }
+    }
     for (name, value) in &ctx.envs {
         cmd.env(name, value);
     }
+    for name in &ctx.env_remove {
+        cmd.env_remove(name);
+    }
     cmd.args(&ctx.args);
-
     #[cfg(unix)]
-    {
+    if let Some(argv0) = &ctx.argv0 {
         use std::os::unix::process::CommandExt;
-        let err = cmd.exec();
-        panic!("cargo-xrun-remote: {}", err);
+        cmd.arg0(argv0);
     }
-
-    #[cfg(windows)]
-    {
-        use std::process::ExitCode;
-        let status = match cmd.status() {
-            Ok(s) => s,
-            Err(err) if err.kind() == std::io::ErrorKind::FileTooLarge => {
-                eprintln!(
-                    r#"cargo-xrun-remote: The executable size exceeds the limit allowed by Windows WebDav Client.
-To raise the limit, update FileSizeLimitInBytes in HKEY_LOCAL_MACHINE\SYSTEM\CurrentControlSet\Services\WebClient\Parameters,
-and then restart the WebClient service."#
-                );
-                return ExitCode::from(1);
+    match &ctx.stdin {
+        StdinMode::Inherit => {}
+        StdinMode::Null => {
+            cmd.stdin(std::process::Stdio::null());
+        }
+        StdinMode::File(path) => match std::fs::File::open(path) {
+            Ok(file) => {
+                cmd.stdin(file);
             }
-            Err(err) => {
+            Err(e) => {
                 eprintln!(
-                    "cargo-xrun-remote: Failed to execute remote binary: {}",
-                    err
+                    "cargo-xrun-remote: Failed to open stdin file {}: {}",
+                    path, e
                 );
-                return ExitCode::from(1);
+                return std::process::ExitCode::from(1);
             }
-        };
-        ExitCode::from(status.code().unwrap_or(1) as u8)
+        },
+    }
+
+    // The agent stays around to report how the executable ended, so it leaves Ctrl-C and friends
+    // from the terminal to the executable, which gets the default dispositions back.
+    #[cfg(unix)]
+    unsafe {
+        use std::os::unix::process::CommandExt;
+        libc::signal(libc::SIGINT, libc::SIG_IGN);
+        libc::signal(libc::SIGQUIT, libc::SIG_IGN);
+        let umask = ctx.umask;
+        cmd.pre_exec(move || {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+            libc::signal(libc::SIGQUIT, libc::SIG_DFL);
+            if let Some(umask) = umask {
+                libc::umask(umask as libc::mode_t);
+            }
+            Ok(())
+        });
+    }
+
+    use std::process::ExitCode;
+    let status = match run(&mut cmd, ctx.timeout_secs) {
+        Ok(s) => s,
+        #[cfg(windows)]
+        Err(err) if err.kind() == std::io::ErrorKind::FileTooLarge => {
+            eprintln!(
+                r#"cargo-xrun-remote: The executable size exceeds the limit allowed by Windows WebDav Client.
+To raise the limit, update FileSizeLimitInBytes in HKEY_LOCAL_MACHINE\SYSTEM\CurrentControlSet\Services\WebClient\Parameters,
+and then restart the WebClient service."#
+            );
+            return ExitCode::from(1);
+        }
+        Err(err) => {
+            eprintln!(
+                "cargo-xrun-remote: Failed to execute remote binary: {}",
+                err
+            );
+            return ExitCode::from(1);
+        }
+    };
+
+    let outcome = match status {
+        Some(status) => outcome::Outcome::from_status(status),
+        None => outcome::Outcome::TimedOut(ctx.timeout_secs.unwrap_or_default()),
+    };
+    if !ctx.report_url.is_empty()
+        && let Err(err) = http::put(&ctx.report_url, outcome.encode().as_bytes())
+    {
+        eprintln!("cargo-xrun-remote: Failed to report exit status: {}", err);
+    }
+    // Only used when the report did not get through.
+    match outcome {
+        outcome::Outcome::Exited(code) => ExitCode::from(code as u8),
+        outcome::Outcome::Signaled(signal) => ExitCode::from(128 + signal as u8),
+        outcome::Outcome::Exception(_) => ExitCode::from(1),
+        outcome::Outcome::TimedOut(_) => ExitCode::from(124),
+    }
+}
+
+/// Runs `cmd` to completion, or kills it once `timeout_secs` have passed and returns `None`.
+#[cfg(feature = "decode")]
+fn run(
+    cmd: &mut std::process::Command,
+    timeout_secs: Option<u64>,
+) -> std::io::Result<Option<std::process::ExitStatus>> {
+    use std::time::{Duration, Instant};
+
+    let Some(timeout_secs) = timeout_secs else {
+        return cmd.status().map(Some);
+    };
+    let mut child = cmd.spawn()?;
+    let deadline = Instant::now() + Duration::from_secs(timeout_secs);
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_millis(20));
     }
 }
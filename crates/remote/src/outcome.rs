@@ -0,0 +1,60 @@
+//! How the remote process ended, as reported by the agent to the host.
+
+/// Termination of the remote process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Exited normally with this code.
+    Exited(i32),
+    /// Killed by this Unix signal.
+    Signaled(i32),
+    /// Terminated by this Windows exception (an NTSTATUS error code).
+    Exception(u32),
+    /// Killed by the agent after running for this many seconds.
+    TimedOut(u64),
+}
+
+impl Outcome {
+    /// Text form sent over the wire, e.g. `exited 3` or `exception c0000005`.
+    pub fn encode(&self) -> String {
+        match self {
+            Outcome::Exited(code) => format!("exited {}", code),
+            Outcome::Signaled(signal) => format!("signaled {}", signal),
+            Outcome::Exception(code) => format!("exception {:08x}", code),
+            Outcome::TimedOut(secs) => format!("timed-out {}", secs),
+        }
+    }
+
+    pub fn decode(encoded: &str) -> Option<Self> {
+        let (kind, value) = encoded.trim().split_once(' ')?;
+        match kind {
+            "exited" => value.parse().ok().map(Outcome::Exited),
+            "signaled" => value.parse().ok().map(Outcome::Signaled),
+            "exception" => u32::from_str_radix(value, 16).ok().map(Outcome::Exception),
+            "timed-out" => value.parse().ok().map(Outcome::TimedOut),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "decode")]
+    pub fn from_status(status: std::process::ExitStatus) -> Self {
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            match (status.code(), status.signal()) {
+                (Some(code), _) => Outcome::Exited(code),
+                (None, Some(signal)) => Outcome::Signaled(signal),
+                (None, None) => Outcome::Exited(1),
+            }
+        }
+        #[cfg(windows)]
+        {
+            let code = status.code().unwrap_or(1) as u32;
+            // NTSTATUS codes with error severity, as left behind by unhandled exceptions.
+            if (0xC000_0000..0xD000_0000).contains(&code) {
+                Outcome::Exception(code)
+            } else {
+                Outcome::Exited(code as i32)
+            }
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! Versioning of the protocol between the host and the agent.
+//!
+//! Every encoded [`ExecContext`](crate::ExecContext) starts with the protocol version and the
+//! capabilities it requires, so an agent that does not match the host refuses the context instead
+//! of misreading it. The host can also ask an agent for both with the `version` subcommand.
+
+use std::fmt;
+
+/// Version of the context encoding and the agent's command line. A host and an agent only work
+/// together when their versions are equal.
+pub const VERSION: u32 = 3;
+
+/// Reports how the executable ended to `report_url`.
+pub const CAP_OUTCOME: u32 = 1 << 0;
+/// Starts the executable with only the given environment when `clear_env` is set.
+pub const CAP_CLEAR_ENV: u32 = 1 << 1;
+/// Has the `cache-lookup`, `cache-put` and `install-self` subcommands.
+pub const CAP_CACHE: u32 = 1 << 2;
+/// Has the `doctor` subcommand.
+pub const CAP_DOCTOR: u32 = 1 << 3;
+/// Applies `argv0`, `env_remove`, `umask`, `stdin` and `timeout_secs`.
+pub const CAP_LAUNCH_OPTIONS: u32 = 1 << 4;
+
+/// Capabilities of this build of the agent.
+pub const CAPABILITIES: u32 =
+    CAP_OUTCOME | CAP_CLEAR_ENV | CAP_CACHE | CAP_DOCTOR | CAP_LAUNCH_OPTIONS;
+
+const CAPABILITY_NAMES: &[(u32, &str)] = &[
+    (CAP_OUTCOME, "outcome"),
+    (CAP_CLEAR_ENV, "clear-env"),
+    (CAP_CACHE, "cache"),
+    (CAP_DOCTOR, "doctor"),
+    (CAP_LAUNCH_OPTIONS, "launch-options"),
+];
+
+/// Names of the capabilities in `capabilities`, such as `outcome, cache`.
+pub fn capability_names(capabilities: u32) -> String {
+    let mut names: Vec<String> = CAPABILITY_NAMES
+        .iter()
+        .filter(|(capability, _)| capabilities & capability != 0)
+        .map(|(_, name)| name.to_string())
+        .collect();
+    let known = CAPABILITY_NAMES
+        .iter()
+        .fold(0, |known, (capability, _)| known | capability);
+    if capabilities & !known != 0 {
+        names.push(format!("{:#x}", capabilities & !known));
+    }
+    names.join(", ")
+}
+
+/// Why an agent cannot serve a host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolError {
+    VersionMismatch { host: u32, agent: u32 },
+    MissingCapabilities(u32),
+    Malformed,
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::VersionMismatch { host, agent } => write!(
+                f,
+                "protocol version mismatch: the host speaks version {}, the agent version {}. \
+                The agent does not belong to this cargo-xrun and needs to be replaced",
+                host, agent
+            ),
+            ProtocolError::MissingCapabilities(missing) => write!(
+                f,
+                "the agent lacks capabilities the host requires: {}",
+                capability_names(*missing)
+            ),
+            ProtocolError::Malformed => write!(f, "malformed protocol header"),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+/// Protocol version and capabilities, as required by a host or offered by an agent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub version: u32,
+    pub capabilities: u32,
+}
+
+impl Header {
+    /// What this build offers as an agent.
+    pub const CURRENT: Header = Header {
+        version: VERSION,
+        capabilities: CAPABILITIES,
+    };
+
+    /// Binary form that prefixes encoded contexts.
+    pub fn to_bytes(self) -> [u8; 8] {
+        let mut bytes = [0; 8];
+        bytes[..4].copy_from_slice(&self.version.to_le_bytes());
+        bytes[4..].copy_from_slice(&self.capabilities.to_le_bytes());
+        bytes
+    }
+
+    /// Splits the header off `bytes`.
+    pub fn split(bytes: &[u8]) -> Result<(Self, &[u8]), ProtocolError> {
+        let (header, rest) = bytes
+            .split_first_chunk::<8>()
+            .ok_or(ProtocolError::Malformed)?;
+        let version = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let capabilities = u32::from_le_bytes(header[4..].try_into().unwrap());
+        Ok((
+            Header {
+                version,
+                capabilities,
+            },
+            rest,
+        ))
+    }
+
+    /// Text form printed by the agent's `version` subcommand, e.g.
+    /// `cargo-xrun-remote protocol 3 capabilities 1f`.
+    pub fn encode(&self) -> String {
+        format!(
+            "cargo-xrun-remote protocol {} capabilities {:x}",
+            self.version, self.capabilities
+        )
+    }
+
+    pub fn decode(encoded: &str) -> Option<Self> {
+        let rest = encoded.trim().strip_prefix("cargo-xrun-remote protocol ")?;
+        let (version, capabilities) = rest.split_once(" capabilities ")?;
+        Some(Header {
+            version: version.parse().ok()?,
+            capabilities: u32::from_str_radix(capabilities, 16).ok()?,
+        })
+    }
+
+    /// Checks that an agent offering `self` serves a host requiring `required`.
+    pub fn serves(&self, required: Header) -> Result<(), ProtocolError> {
+        if self.version != required.version {
+            return Err(ProtocolError::VersionMismatch {
+                host: required.version,
+                agent: self.version,
+            });
+        }
+        let missing = required.capabilities & !self.capabilities;
+        if missing != 0 {
+            return Err(ProtocolError::MissingCapabilities(missing));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header() {
+        let header = Header::CURRENT;
+        assert_eq!(
+            Header::decode(&format!("{}\n", header.encode())),
+            Some(header)
+        );
+        assert_eq!(Header::decode("Usage: cargo-xrun-remote"), None);
+
+        let bytes = [&header.to_bytes()[..], b"ctx"].concat();
+        let (split, rest) = Header::split(&bytes).unwrap();
+        assert_eq!((split, rest), (header, &b"ctx"[..]));
+        assert_eq!(Header::split(b"short"), Err(ProtocolError::Malformed));
+
+        let required = |version, capabilities| Header {
+            version,
+            capabilities,
+        };
+        assert_eq!(header.serves(required(VERSION, CAP_OUTCOME)), Ok(()));
+        assert_eq!(
+            header.serves(required(VERSION + 1, 0)),
+            Err(ProtocolError::VersionMismatch {
+                host: VERSION + 1,
+                agent: VERSION
+            })
+        );
+        let old_agent = required(VERSION, CAP_OUTCOME);
+        let err = old_agent
+            .serves(required(VERSION, CAP_CACHE | CAP_DOCTOR))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the agent lacks capabilities the host requires: cache, doctor"
+        );
+    }
+}
@@ -0,0 +1,95 @@
+//! Tokens of the host's sessions.
+//!
+//! The host's file server only answers requests that carry the session's token. The host hands
+//! the token to the agent once per session, over stdin, and contexts only name the session, so
+//! the token never shows up on a command line, where other users of the machine could read it.
+//! Tokens are kept in `<agent dir>/sessions/<session id>`, readable only by the user.
+
+use std::{
+    fs,
+    io::{self, Read as _},
+    path::PathBuf,
+    process::ExitCode,
+    time::SystemTime,
+};
+
+use crate::cache;
+
+fn token_path(session: &str) -> io::Result<PathBuf> {
+    if session.is_empty() || !session.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid session id: {:?}", session),
+        ));
+    }
+    Ok(cache::agent_dir()?.join("sessions").join(session))
+}
+
+/// The token of `session`, as stored by `session-open`.
+pub fn token(session: &str) -> io::Result<String> {
+    let path = token_path(session)?;
+    let token = fs::read_to_string(&path)?;
+    // Mark the session as used, so that garbage collection leaves it alone.
+    let _ = fs::File::options()
+        .append(true)
+        .open(&path)
+        .and_then(|file| file.set_modified(SystemTime::now()));
+    Ok(token)
+}
+
+/// `session-open <session id>`: stores the token read from stdin for the session.
+pub fn open(args: &[String]) -> ExitCode {
+    let [session] = args else {
+        eprintln!("cargo-xrun-remote: usage: session-open <session id> < <token>");
+        return ExitCode::from(1);
+    };
+    cache::mark_used();
+    match store(session) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("cargo-xrun-remote: Failed to open session: {}", err);
+            ExitCode::from(1)
+        }
+    }
+}
+
+fn store(session: &str) -> io::Result<()> {
+    let mut token = String::new();
+    io::stdin().lock().read_to_string(&mut token)?;
+    let token = token.trim();
+    if token.is_empty() || !token.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Expected the session token on stdin",
+        ));
+    }
+
+    let path = token_path(session)?;
+    let dir = path.parent().unwrap();
+    fs::create_dir_all(dir)?;
+    let mut options = fs::File::options();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt as _, PermissionsExt as _};
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+        options.mode(0o600);
+    }
+    io::Write::write_all(&mut options.open(&path)?, token.as_bytes())
+}
+
+/// `session-close <session id>`: forgets the token of the session.
+pub fn close(args: &[String]) -> ExitCode {
+    let [session] = args else {
+        eprintln!("cargo-xrun-remote: usage: session-close <session id>");
+        return ExitCode::from(1);
+    };
+    match token_path(session).and_then(fs::remove_file) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("cargo-xrun-remote: Failed to close session: {}", err);
+            ExitCode::from(1)
+        }
+    }
+}
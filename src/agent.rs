@@ -0,0 +1,303 @@
+use std::process::Stdio;
+
+use anyhow::Context as _;
+use cargo_xrun_remote::protocol::{self, Header};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt as _;
+
+use crate::{embedded_binaries::AgentBinary, ssh_master::SshMaster, target::TargetOs};
+
+/// Shell command that prints where the agent with `hash` is installed, and exits with 2 when it is
+/// not installed there yet.
+fn probe_script(os: TargetOs, hash: &str, remote_dir: Option<&str>) -> String {
+    match os {
+        TargetOs::Windows => {
+            let dir = remote_dir.unwrap_or(r"%LOCALAPPDATA%\cargo-xrun");
+            format!(r#"echo {dir}\agent-{hash}.exe& if not exist "{dir}\agent-{hash}.exe" exit 2"#)
+        }
+        TargetOs::Linux => {
+            let dir = remote_dir.map_or_else(
+                || r#""${XDG_CACHE_HOME:-$HOME/.cache}/cargo-xrun""#.to_string(),
+                |dir| os.quote_arg(dir),
+            );
+            format!(r#"agent={dir}/agent-{hash}; echo "$agent"; [ -x "$agent" ] || exit 2"#)
+        }
+    }
+}
+
+/// Shell command that installs the agent streamed to its stdin at `path`. On Windows, the agent's
+/// `install-self` does the same. Agents of other versions of cargo-xrun next to it are left alone,
+/// since those may still be in use: the agents remove the ones nobody ran for a while.
+fn linux_install_script(path: &str) -> String {
+    format!(
+        concat!(
+            r#"set -e; agent={}; mkdir -p "$(dirname "$agent")"; "#,
+            r#"cat > "$agent.part-$$"; chmod +x "$agent.part-$$"; mv -f "$agent.part-$$" "$agent""#
+        ),
+        TargetOs::Linux.quote_arg(path)
+    )
+}
+
+/// What the probe found at the install location.
+#[derive(Debug, PartialEq, Eq)]
+enum Probe {
+    /// The current agent is installed at this path.
+    Installed(String),
+    /// The current agent still has to be installed at this path.
+    Missing(String),
+}
+
+impl Probe {
+    fn parse(status: Option<i32>, stdout: &[u8]) -> anyhow::Result<Self> {
+        let path = std::str::from_utf8(stdout)
+            .ok()
+            .map(|path| path.trim().to_string())
+            .filter(|path| !path.is_empty());
+        match (status, path) {
+            (Some(0), Some(path)) => Ok(Probe::Installed(path)),
+            (Some(2), Some(path)) => Ok(Probe::Missing(path)),
+            (Some(0 | 2), None) => anyhow::bail!("The probe did not report the install location"),
+            (status, _) => anyhow::bail!("The probe failed (status {:?})", status),
+        }
+    }
+}
+
+/// The agent as installed in the per-user cache directory of the remote.
+pub struct RemoteAgent {
+    /// Path the remote shell invokes the agent with.
+    path: String,
+}
+
+impl RemoteAgent {
+    /// Makes sure `agent` is installed on the remote.
+    ///
+    /// Agents are installed as `agent-<hash>` in `remote_dir`, or by default the per-user cache
+    /// directory (`~/.cache/cargo-xrun` or `%LOCALAPPDATA%\cargo-xrun`), keyed by a hash of the
+    /// binary. The agent keeps its own caches next to itself. A remote that already has the current
+    /// agent costs a single round trip; a missing or outdated one is replaced by installing the
+    /// binary under its new hash. The agents remove outdated ones once unused for a while.
+    pub async fn prepare(
+        ssh_master: &SshMaster,
+        agent: &AgentBinary,
+        os: TargetOs,
+        remote_dir: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let hash = Sha256::digest(&agent.bytes)
+            .iter()
+            .take(8)
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+
+        let probe = ssh_master
+            .command()
+            .arg(probe_script(os, &hash, remote_dir))
+            .stderr(Stdio::inherit())
+            .output()
+            .await
+            .context("Failed to spawn ssh for agent probe")?;
+        let path = match Probe::parse(probe.status.code(), &probe.stdout)
+            .with_context(|| format!("Failed to probe for the agent on {}", ssh_master.name()))?
+        {
+            Probe::Installed(path) => return Ok(Self { path }),
+            Probe::Missing(path) => path,
+        };
+
+        let path = match os {
+            // The agent is reachable through the share, so let it copy itself into place.
+            TargetOs::Windows => {
+                let output = ssh_master
+                    .command()
+                    .arg(format!(
+                        "\\\\localhost@{}\\DavWWWRoot\\remote-bin\\{}",
+                        ssh_master.remote_port(),
+                        agent.file_name
+                    ))
+                    .args(["install-self", &hash])
+                    .args(remote_dir.map(|dir| os.quote_arg(dir)))
+                    .stderr(Stdio::inherit())
+                    .output()
+                    .await
+                    .context("Failed to spawn ssh for agent install")?;
+                if !output.status.success() {
+                    anyhow::bail!(
+                        "Failed to install the agent on {} (status {:?})",
+                        ssh_master.name(),
+                        output.status
+                    );
+                }
+                String::from_utf8(output.stdout)
+                    .ok()
+                    .map(|path| path.trim().to_string())
+                    .filter(|path| !path.is_empty())
+                    .context("Agent install did not report its path")?
+            }
+            // Without a WebDAV client, the agent is streamed over the master connection instead.
+            TargetOs::Linux => {
+                let mut child = ssh_master
+                    .command()
+                    .arg(linux_install_script(&path))
+                    .stdin(Stdio::piped())
+                    .spawn()
+                    .context("Failed to spawn ssh for agent install")?;
+                let mut stdin = child.stdin.take().unwrap();
+                stdin.write_all(&agent.bytes).await?;
+                drop(stdin);
+
+                let status = child.wait().await?;
+                if !status.success() {
+                    anyhow::bail!(
+                        "Failed to install the agent on {} (status {:?})",
+                        ssh_master.name(),
+                        status
+                    );
+                }
+                path
+            }
+        };
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Hands `token` to the agent for the session `session`, over stdin rather than on the command
+    /// line, where other users of the remote could read it.
+    pub async fn open_session(
+        &self,
+        ssh_master: &SshMaster,
+        os: TargetOs,
+        session: &str,
+        token: &str,
+    ) -> anyhow::Result<()> {
+        let mut child = ssh_master
+            .command()
+            .arg(os.quote_arg(&self.path))
+            .args(["session-open", session])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .context("Failed to spawn ssh for session-open")?;
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(token.as_bytes()).await?;
+        drop(stdin);
+
+        let status = child.wait().await?;
+        if !status.success() {
+            anyhow::bail!(
+                "Failed to open the session on {} (status {:?})",
+                ssh_master.name(),
+                status
+            );
+        }
+        Ok(())
+    }
+
+    /// Asks the agent for the protocol version it speaks and the capabilities it has.
+    pub async fn version(&self, ssh_master: &SshMaster, os: TargetOs) -> anyhow::Result<Header> {
+        let output = ssh_master
+            .command()
+            .arg(os.quote_arg(&self.path))
+            .arg("version")
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .context("Failed to spawn ssh for agent version")?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        match Header::decode(&stdout) {
+            Some(header) if output.status.success() => Ok(header),
+            _ => anyhow::bail!(
+                "The agent at {} on {} does not report a protocol version, so it predates this \
+                cargo-xrun (status {:?}): {}",
+                self.path,
+                ssh_master.name(),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        }
+    }
+
+    /// Fails unless the agent speaks this cargo-xrun's protocol version and has `capabilities`.
+    pub async fn check_version(
+        &self,
+        ssh_master: &SshMaster,
+        os: TargetOs,
+        capabilities: u32,
+    ) -> anyhow::Result<Header> {
+        let header = self.version(ssh_master, os).await?;
+        header
+            .serves(Header {
+                version: protocol::VERSION,
+                capabilities,
+            })
+            .with_context(|| format!("Cannot use the agent at {}", self.path))?;
+        Ok(header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_parse() {
+        assert_eq!(
+            Probe::parse(Some(0), b"/home/jo/.cache/cargo-xrun/agent-01\n").unwrap(),
+            Probe::Installed("/home/jo/.cache/cargo-xrun/agent-01".into())
+        );
+        assert_eq!(
+            Probe::parse(Some(2), b"C:\\Users\\jo\\agent-01.exe\r\n").unwrap(),
+            Probe::Missing(r"C:\Users\jo\agent-01.exe".into())
+        );
+        assert!(Probe::parse(Some(0), b"").is_err());
+        assert!(Probe::parse(Some(127), b"").is_err());
+        assert!(Probe::parse(None, b"/agent").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_linux_install() {
+        let dir = tempfile::tempdir().unwrap();
+        let remote_dir = dir.path().join("remote dir");
+        let remote_dir = remote_dir.to_str().unwrap();
+        let sh = |script: String, stdin: &[u8]| {
+            use std::io::Write as _;
+            let mut child = std::process::Command::new("sh")
+                .args(["-c", &script])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+            child.stdin.take().unwrap().write_all(stdin).unwrap();
+            let output = child.wait_with_output().unwrap();
+            (output.status.code(), output.stdout)
+        };
+
+        let (status, stdout) = sh(probe_script(TargetOs::Linux, "01", Some(remote_dir)), b"");
+        let path = format!("{}/agent-01", remote_dir);
+        assert_eq!(
+            Probe::parse(status, &stdout).unwrap(),
+            Probe::Missing(path.clone())
+        );
+
+        std::fs::create_dir_all(remote_dir).unwrap();
+        std::fs::write(format!("{}/agent-00", remote_dir), "").unwrap();
+        std::fs::write(format!("{}/agent-02.part-1", remote_dir), "").unwrap();
+        let (status, _) = sh(linux_install_script(&path), b"#!/bin/sh\n");
+        assert_eq!(status, Some(0));
+        assert_eq!(std::fs::read(&path).unwrap(), b"#!/bin/sh\n");
+        let mut names = std::fs::read_dir(remote_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["agent-00", "agent-01", "agent-02.part-1"]);
+
+        let (status, stdout) = sh(probe_script(TargetOs::Linux, "01", Some(remote_dir)), b"");
+        assert_eq!(
+            Probe::parse(status, &stdout).unwrap(),
+            Probe::Installed(path)
+        );
+    }
+}
@@ -1,15 +1,255 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
 use anyhow::{Context, anyhow};
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Host {
     pub destination: String,
     pub targets: Vec<String>,
+    /// Human-readable name shown instead of the destination.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    /// SSH port, overriding `~/.ssh/config`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// Private key to authenticate with (`ssh -i`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity_file: Option<PathBuf>,
+    /// Jump hosts to connect through, in order (`ssh -J`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub proxy_jump: Vec<String>,
+    /// Extra `ssh -o` options, such as `ServerAliveInterval=30`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ssh_options: Vec<String>,
+    /// Directory on the remote for the agent, uploaded executables and files fetched from the
+    /// host, instead of the per-user cache directory. Relative paths start at the remote user's
+    /// home directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_dir: Option<String>,
+    /// Environment variables for the remote process, unless forwarded from the host or set with
+    /// `--env`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "EnvForward::is_empty")]
+    pub env_forward: EnvForward,
+}
+
+impl Host {
+    /// Name to show for the host: its alias if it has one, its destination otherwise.
+    pub fn name(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.destination)
+    }
+}
+
+/// Settings that apply to a target triple regardless of the host it runs on.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TargetSettings {
+    #[serde(default, skip_serializing_if = "EnvForward::is_empty")]
+    pub env_forward: EnvForward,
+    /// How to pick among several hosts assigned to the target.
+    #[serde(default, skip_serializing_if = "PoolStrategy::is_default")]
+    pub pool_strategy: PoolStrategy,
+    /// Agent executable to install on the target's hosts instead of the embedded one, for
+    /// platforms whose agent is not embedded in this build.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<PathBuf>,
+}
+
+/// How to pick a host when several are assigned to the same target. Unreachable hosts are always
+/// skipped.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PoolStrategy {
+    /// The first host in the config file.
+    #[default]
+    First,
+    /// The host used least recently.
+    Lru,
+    /// The host that connects first, probing all hosts at once.
+    Fastest,
+}
+
+impl PoolStrategy {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Which host environment variables reach the remote process. `CARGO_*` variables are always
+/// forwarded unless denied.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct EnvForward {
+    /// Glob patterns (`*`, `?`) of variable names to forward.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+    /// Glob patterns of variable names never to forward. Takes precedence over `allow`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+    /// Start the remote process with only the forwarded variables instead of on top of the
+    /// remote user's environment.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hermetic: bool,
+}
+
+impl EnvForward {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Combines two policies; the result forwards and denies what either of them does.
+    pub fn merge(&mut self, other: EnvForward) {
+        self.allow.extend(other.allow);
+        self.deny.extend(other.deny);
+        self.hermetic |= other.hermetic;
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct ConfigFile {
     host: Vec<Host>,
+    /// Host directories exported to remotes in addition to the workspace, the target directory,
+    /// `CARGO_HOME` and the toolchain sysroot.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exported_paths: Vec<PathBuf>,
+    #[serde(default, skip_serializing_if = "PathArgs::is_empty")]
+    pub path_args: PathArgs,
+    /// Per-target settings, keyed by target triple.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub target: BTreeMap<String, TargetSettings>,
+}
+
+impl ConfigFile {
+    pub fn hosts(&self) -> &[Host] {
+        &self.host
+    }
+
+    /// Index of the host whose alias or destination is `name`, preferring aliases.
+    fn host_index(&self, name: &str) -> anyhow::Result<usize> {
+        self.host
+            .iter()
+            .position(|host| host.alias.as_deref() == Some(name))
+            .or_else(|| self.host.iter().position(|host| host.destination == name))
+            .ok_or_else(|| anyhow!("No host with alias or destination '{}'", name))
+    }
+
+    /// The host whose alias or destination is `name`.
+    pub fn find_host(&self, name: &str) -> Option<&Host> {
+        let index = self.host_index(name).ok()?;
+        Some(&self.host[index])
+    }
+
+    /// Fails if the alias or destination of `host` names another host than the one at `index`,
+    /// which would make it ambiguous which host `--host` and `assign` refer to.
+    fn check_names(&self, host: &Host, index: Option<usize>) -> anyhow::Result<()> {
+        let names = |host: &Host| {
+            [Some(host.destination.clone()), host.alias.clone()]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>()
+        };
+        let new_names = names(host);
+        for (i, existing) in self.host.iter().enumerate() {
+            if Some(i) == index {
+                continue;
+            }
+            if let Some(name) = names(existing)
+                .into_iter()
+                .find(|name| new_names.contains(name))
+            {
+                anyhow::bail!("Host '{}' already goes by '{}'", existing.name(), name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Changes the host whose alias or destination is `name` with `edit`, unless that leaves it
+    /// with the alias or destination of another host.
+    pub fn edit_host(&mut self, name: &str, edit: impl FnOnce(&mut Host)) -> anyhow::Result<()> {
+        let index = self.host_index(name)?;
+        let mut host = self.host[index].clone();
+        edit(&mut host);
+        self.check_names(&host, Some(index))?;
+        self.host[index] = host;
+        Ok(())
+    }
+
+    /// Adds `host`, moving its targets off the hosts they were assigned to before.
+    pub fn add_host(&mut self, host: Host) -> anyhow::Result<()> {
+        self.check_names(&host, None)?;
+        for target in &host.targets {
+            self.unassign(target);
+        }
+        self.host.push(host);
+        Ok(())
+    }
+
+    pub fn remove_host(&mut self, name: &str) -> anyhow::Result<Host> {
+        let index = self.host_index(name)?;
+        Ok(self.host.remove(index))
+    }
+
+    /// Assigns `target` to the host `name`. With `pool`, the host joins the hosts the target is
+    /// already assigned to; otherwise it replaces them.
+    pub fn assign(&mut self, target: &str, name: &str, pool: bool) -> anyhow::Result<()> {
+        let index = self.host_index(name)?;
+        if !pool {
+            self.unassign(target);
+        }
+        if !self.host[index].targets.iter().any(|t| t == target) {
+            self.host[index].targets.push(target.to_string());
+        }
+        Ok(())
+    }
+
+    /// Removes `target` from all hosts it is assigned to, returning their names.
+    pub fn unassign(&mut self, target: &str) -> Vec<String> {
+        let mut names = Vec::new();
+        for host in &mut self.host {
+            if host.targets.iter().any(|t| t == target) {
+                host.targets.retain(|t| t != target);
+                names.push(host.name().to_string());
+            }
+        }
+        names
+    }
+
+    /// The hosts `target` is assigned to, in config order.
+    pub fn hosts_for(&self, target: &str) -> Vec<Host> {
+        self.host
+            .iter()
+            .filter(|host| host.targets.iter().any(|t| t == target))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Which arguments of the target binary are host paths to translate for the remote.
+///
+/// Both settings apply to a whole argument, or to the value of a `--name=value` argument.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathArgs {
+    /// Translate arguments that name an existing host path and contain a path separator.
+    #[serde(default)]
+    pub detect_existing: bool,
+    /// Glob patterns (`*`, `?`) of arguments to translate whether or not the path exists.
+    #[serde(default)]
+    pub patterns: Vec<String>,
+}
+
+impl PathArgs {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Parses the JSON config, treating an empty string as an empty config.
+pub fn parse(json_config_str: &str) -> anyhow::Result<ConfigFile> {
+    if json_config_str.trim().is_empty() {
+        Ok(ConfigFile::default())
+    } else {
+        serde_json::from_str(json_config_str).context("Failed to parse JSON configuration")
+    }
 }
 
 pub enum UserResponse {
@@ -32,12 +272,7 @@ pub fn upsert_with(
     target: &str,
     f: impl FnOnce(&[Host]) -> anyhow::Result<UserResponse>,
 ) -> anyhow::Result<Host> {
-    // Parse JSON config, treating empty string as empty host list
-    let mut config: ConfigFile = if json_config_str.trim().is_empty() {
-        ConfigFile::default()
-    } else {
-        serde_json::from_str(json_config_str).context("Failed to parse JSON configuration")?
-    };
+    let mut config = parse(json_config_str)?;
 
     // Check if target already exists in any host
     for host in &config.host {
@@ -80,6 +315,7 @@ pub fn upsert_with(
                 let new_host = Host {
                     destination,
                     targets: vec![target.to_string()],
+                    ..Default::default()
                 };
                 config.host.push(new_host.clone());
 
@@ -354,6 +590,227 @@ mod tests {
         assert!(err_msg.contains("Invalid host_index"));
     }
 
+    #[test]
+    fn test_edit_hosts() {
+        let mut config = parse(
+            r#"{
+  "host": [
+    {
+      "destination": "user@server1.com",
+      "targets": ["aarch64-unknown-linux-gnu", "x86_64-unknown-linux-gnu"],
+      "alias": "arm"
+    }
+  ]
+}"#,
+        )
+        .unwrap();
+
+        config
+            .add_host(Host {
+                destination: "user@server2.com".into(),
+                targets: vec!["x86_64-unknown-linux-gnu".into()],
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(config.hosts()[0].targets, ["aarch64-unknown-linux-gnu"]);
+        assert!(
+            config
+                .add_host(Host {
+                    destination: "user@server2.com".into(),
+                    ..Default::default()
+                })
+                .is_err()
+        );
+
+        config
+            .assign("aarch64-unknown-linux-gnu", "user@server2.com", false)
+            .unwrap();
+        assert!(config.hosts()[0].targets.is_empty());
+        assert_eq!(
+            config.hosts()[1].targets,
+            ["x86_64-unknown-linux-gnu", "aarch64-unknown-linux-gnu"]
+        );
+        assert!(
+            config
+                .assign("aarch64-unknown-linux-gnu", "unknown", false)
+                .is_err()
+        );
+
+        // Pools
+        config
+            .assign("x86_64-unknown-linux-gnu", "arm", true)
+            .unwrap();
+        assert_eq!(
+            config
+                .hosts_for("x86_64-unknown-linux-gnu")
+                .iter()
+                .map(Host::name)
+                .collect::<Vec<_>>(),
+            ["arm", "user@server2.com"]
+        );
+        assert_eq!(
+            config.unassign("x86_64-unknown-linux-gnu"),
+            ["arm", "user@server2.com"]
+        );
+        assert!(config.unassign("x86_64-unknown-linux-gnu").is_empty());
+
+        assert_eq!(
+            config.remove_host("arm").unwrap().destination,
+            "user@server1.com"
+        );
+        assert_eq!(config.hosts().len(), 1);
+    }
+
+    #[test]
+    fn test_host_names_are_unique() {
+        let mut config = parse(
+            r#"{
+  "host": [
+    { "destination": "user@server1.com", "targets": [], "alias": "arm" },
+    { "destination": "user@server2.com", "targets": [] }
+  ]
+}"#,
+        )
+        .unwrap();
+
+        let host = |destination: &str, alias: Option<&str>| Host {
+            destination: destination.into(),
+            alias: alias.map(str::to_string),
+            ..Default::default()
+        };
+        assert!(
+            config
+                .add_host(host("user@server3.com", Some("arm")))
+                .is_err()
+        );
+        assert!(config.add_host(host("arm", None)).is_err());
+        assert!(
+            config
+                .add_host(host("user@server3.com", Some("user@server2.com")))
+                .is_err()
+        );
+        config
+            .add_host(host("user@server3.com", Some("x86")))
+            .unwrap();
+
+        assert!(
+            config
+                .edit_host("user@server2.com", |host| host.alias = Some("arm".into()))
+                .is_err()
+        );
+        assert!(
+            config
+                .edit_host("x86", |host| host.destination = "user@server1.com".into())
+                .is_err()
+        );
+        assert_eq!(config.hosts()[1].alias, None);
+        assert_eq!(config.hosts()[2].destination, "user@server3.com");
+        // A host keeps its own names.
+        config
+            .edit_host("arm", |host| host.port = Some(2222))
+            .unwrap();
+        config.edit_host("x86", |host| host.alias = None).unwrap();
+        assert_eq!(config.hosts()[0].port, Some(2222));
+        assert_eq!(config.hosts()[2].name(), "user@server3.com");
+    }
+
+    #[test]
+    fn test_parse_host_settings() {
+        let config = parse(
+            r#"{
+  "host": [
+    {
+      "destination": "ci@10.0.0.7",
+      "targets": ["x86_64-pc-windows-msvc"],
+      "alias": "windows-ci",
+      "port": 2222,
+      "identity_file": "~/.ssh/ci_ed25519",
+      "proxy_jump": ["bastion.example.com"],
+      "ssh_options": ["ServerAliveInterval=30"],
+      "remote_dir": "D:\\xrun",
+      "env": { "RUST_BACKTRACE": "1" }
+    },
+    {
+      "destination": "user@server1.com",
+      "targets": ["aarch64-unknown-linux-gnu"]
+    }
+  ]
+}"#,
+        )
+        .unwrap();
+
+        let host = &config.host[0];
+        assert_eq!(host.name(), "windows-ci");
+        assert_eq!(host.port, Some(2222));
+        assert_eq!(host.remote_dir.as_deref(), Some("D:\\xrun"));
+        assert_eq!(host.env["RUST_BACKTRACE"], "1");
+        assert_eq!(config.host[1].name(), "user@server1.com");
+
+        // Unset settings are left out when the config is written back.
+        let json = serde_json::to_string(&config.host[1]).unwrap();
+        assert_eq!(
+            json,
+            r#"{"destination":"user@server1.com","targets":["aarch64-unknown-linux-gnu"]}"#
+        );
+    }
+
+    #[test]
+    fn test_parse_env_forward() {
+        let config = parse(
+            r#"{
+  "host": [
+    {
+      "destination": "user@server1.com",
+      "targets": ["aarch64-unknown-linux-gnu"],
+      "env_forward": { "allow": ["RUST_*"], "deny": ["RUST_MIN_STACK"] }
+    }
+  ],
+  "target": {
+    "aarch64-unknown-linux-gnu": {
+      "env_forward": { "hermetic": true },
+      "agent": "/opt/agents/cargo-xrun-remote-aarch64"
+    }
+  }
+}"#,
+        )
+        .unwrap();
+
+        let mut env_forward = config.host[0].env_forward.clone();
+        env_forward.merge(config.target["aarch64-unknown-linux-gnu"].env_forward.clone());
+        assert_eq!(
+            env_forward,
+            EnvForward {
+                allow: vec!["RUST_*".into()],
+                deny: vec!["RUST_MIN_STACK".into()],
+                hermetic: true,
+            }
+        );
+        assert_eq!(
+            config.target["aarch64-unknown-linux-gnu"].agent,
+            Some(PathBuf::from("/opt/agents/cargo-xrun-remote-aarch64"))
+        );
+    }
+
+    #[test]
+    fn test_upsert_with_keeps_exported_paths() {
+        let mut config_str = String::from(
+            r#"{
+  "host": [],
+  "exported_paths": ["/opt/fixtures"]
+}"#,
+        );
+
+        upsert_with(&mut config_str, "x86_64-unknown-linux-gnu", |_hosts| {
+            Ok(UserResponse::AddNewHost {
+                destination: "user@server1.com".to_string(),
+            })
+        })
+        .expect("test should succeed");
+
+        let config = parse(&config_str).unwrap();
+        assert_eq!(config.exported_paths, vec![PathBuf::from("/opt/fixtures")]);
+    }
+
     #[test]
     fn test_upsert_with_add_new_host_merges_existing_destination() {
         let mut config_str = String::from(
@@ -0,0 +1,150 @@
+//! Settings from `[workspace.metadata.xrun]` and `[package.metadata.xrun]` in Cargo manifests.
+//!
+//! ```toml
+//! [workspace.metadata.xrun]
+//! builder = "cargo-zigbuild"
+//! exported_paths = ["../fixtures"]
+//! env = { RUST_BACKTRACE = "1" }
+//! env_forward = { allow = ["RUST_LOG"] }
+//!
+//! [workspace.metadata.xrun.target.x86_64-pc-windows-msvc]
+//! builder = "cargo-xwin"
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context as _;
+use serde::Deserialize;
+
+use super::{EnvForward, PathArgs};
+use crate::workspace::Metadata;
+
+/// Settings a manifest can make, either for all targets or for one.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ManifestSettings {
+    /// Command for building, as with `--builder`.
+    pub builder: Option<String>,
+    /// Host directories to export, relative to the manifest's directory.
+    pub exported_paths: Vec<PathBuf>,
+    pub path_args: PathArgs,
+    pub env_forward: EnvForward,
+    /// Environment variables for the remote process, unless forwarded from the host, set with
+    /// `--env` or set by the host's `env` in the user config.
+    pub env: BTreeMap<String, String>,
+}
+
+impl ManifestSettings {
+    /// Layers `other` over `self`: `other` wins for single values, lists are combined.
+    fn merge(&mut self, other: ManifestSettings) {
+        if other.builder.is_some() {
+            self.builder = other.builder;
+        }
+        self.exported_paths.extend(other.exported_paths);
+        self.path_args.detect_existing |= other.path_args.detect_existing;
+        self.path_args.patterns.extend(other.path_args.patterns);
+        self.env_forward.merge(other.env_forward);
+        self.env.extend(other.env);
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct ManifestConfig {
+    #[serde(flatten)]
+    settings: ManifestSettings,
+    /// Per-target settings, keyed by target triple, layered over the settings for all targets.
+    target: BTreeMap<String, ManifestSettings>,
+}
+
+/// Parses the `xrun` table of a manifest's `metadata` and resolves the settings for `target`.
+fn parse(
+    metadata: &serde_json::Value,
+    manifest_dir: &Path,
+    target: &str,
+) -> anyhow::Result<ManifestSettings> {
+    let Some(xrun) = metadata.get("xrun") else {
+        return Ok(ManifestSettings::default());
+    };
+    let mut config = ManifestConfig::deserialize(xrun)?;
+    let mut settings = config.settings;
+    if let Some(target_settings) = config.target.remove(target) {
+        settings.merge(target_settings);
+    }
+    for path in &mut settings.exported_paths {
+        *path = manifest_dir.join(&*path);
+    }
+    Ok(settings)
+}
+
+/// Settings for `target` from the workspace manifest, overridden by those of the package in the
+/// current directory.
+pub fn load_for_target(metadata: &Metadata, target: &str) -> anyhow::Result<ManifestSettings> {
+    let mut settings = parse(&metadata.metadata, &metadata.workspace_root, target)
+        .context("Invalid [workspace.metadata.xrun] in the workspace manifest")?;
+
+    let cwd = std::env::current_dir().context("Failed to get current directory")?;
+    let package = metadata
+        .packages
+        .iter()
+        .filter_map(|package| Some((package, package.manifest_path.parent()?)))
+        .filter(|(_, manifest_dir)| cwd.starts_with(manifest_dir))
+        .max_by_key(|(_, manifest_dir)| manifest_dir.components().count());
+    if let Some((package, manifest_dir)) = package {
+        settings.merge(
+            parse(&package.metadata, manifest_dir, target).with_context(|| {
+                format!(
+                    "Invalid [package.metadata.xrun] in {}",
+                    package.manifest_path.display()
+                )
+            })?,
+        );
+    }
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_layers_target_settings() {
+        let metadata = serde_json::json!({
+            "xrun": {
+                "builder": "cargo-zigbuild",
+                "exported_paths": ["fixtures"],
+                "env": { "RUST_BACKTRACE": "1", "RUST_LOG": "info" },
+                "target": {
+                    "x86_64-pc-windows-msvc": {
+                        "builder": "cargo-xwin",
+                        "env": { "RUST_LOG": "debug" }
+                    }
+                }
+            }
+        });
+
+        let settings = parse(&metadata, Path::new("/work"), "x86_64-pc-windows-msvc").unwrap();
+        assert_eq!(settings.builder.as_deref(), Some("cargo-xwin"));
+        assert_eq!(settings.exported_paths, [PathBuf::from("/work/fixtures")]);
+        assert_eq!(settings.env["RUST_BACKTRACE"], "1");
+        assert_eq!(settings.env["RUST_LOG"], "debug");
+
+        let settings = parse(&metadata, Path::new("/work"), "aarch64-unknown-linux-gnu").unwrap();
+        assert_eq!(settings.builder.as_deref(), Some("cargo-zigbuild"));
+        assert_eq!(settings.env["RUST_LOG"], "info");
+
+        let settings = parse(&serde_json::Value::Null, Path::new("/work"), "x").unwrap();
+        assert!(settings.builder.is_none());
+        assert!(
+            parse(
+                &serde_json::json!({ "xrun": { "env": [] } }),
+                Path::new("/"),
+                "x"
+            )
+            .is_err()
+        );
+    }
+}
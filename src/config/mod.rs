@@ -1,9 +1,12 @@
 mod config_file;
+pub mod manifest;
+mod store;
 
-use std::{fmt::Display, fs::File, io::{Read as _, Seek as _, SeekFrom, Write as _}};
+use std::{fmt::Display, io::IsTerminal as _, path::PathBuf};
 
 use anyhow::Context as _;
-use config_file::{upsert_with, Host, UserResponse};
+use config_file::{upsert_with, UserResponse};
+pub use config_file::{ConfigFile, EnvForward, Host, PathArgs, PoolStrategy};
 use inquire::{InquireError, Select, Text, error::InquireResult, validator::Validation};
 
 fn prompt_for_host_selection(
@@ -27,21 +30,28 @@ fn prompt_for_host_selection(
     }
 
     // Build options: existing hosts + "Add new host"
-    enum SelectOption {
-        ExistingHost { host: Host, index: usize },
+    enum SelectOption<'a> {
+        ExistingHost { host: &'a Host, index: usize },
         AddNewHost,
     }
-    impl Display for SelectOption {
+    impl Display for SelectOption<'_> {
         fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
             match self {
-                SelectOption::ExistingHost { host, .. } => {
-                    write!(
+                SelectOption::ExistingHost { host, .. } => match &host.alias {
+                    Some(alias) => write!(
+                        f,
+                        "{} ({}, targets: {})",
+                        alias,
+                        host.destination,
+                        host.targets.join(", ")
+                    ),
+                    None => write!(
                         f,
                         "{} (targets: {})",
                         host.destination,
                         host.targets.join(", ")
-                    )
-                }
+                    ),
+                },
                 SelectOption::AddNewHost => write!(f, "Add a new remote host"),
             }
         }
@@ -49,10 +59,7 @@ fn prompt_for_host_selection(
     let mut options: Vec<SelectOption> = existing_hosts
         .iter()
         .enumerate()
-        .map(|(index, host)| SelectOption::ExistingHost {
-            host: host.clone(),
-            index,
-        })
+        .map(|(index, host)| SelectOption::ExistingHost { host, index })
         .collect();
     options.push(SelectOption::AddNewHost);
 
@@ -81,28 +88,91 @@ fn prompt_for_host_selection(
     }
 }
 
-pub fn get_ssh_destination(target: &str) -> anyhow::Result<String> {
+/// Settings resolved from the config file for one target.
+pub struct TargetConfig {
+    /// The hosts the target may run on, in config order. Never empty.
+    pub hosts: Vec<Host>,
+    pub pool_strategy: PoolStrategy,
+    pub exported_paths: Vec<PathBuf>,
+    pub path_args: PathArgs,
+    /// The target's policy, to be combined with that of the host it runs on.
+    pub env_forward: EnvForward,
+    /// Agent to install instead of the embedded one.
+    pub agent: Option<PathBuf>,
+}
+
+pub fn config_path() -> anyhow::Result<PathBuf> {
     let config_dir = dirs::config_dir()
         .ok_or_else(|| anyhow::anyhow!("Could not determine config directory"))?
         .join("cargo-xrun");
-    std::fs::create_dir_all(&config_dir)?;
+    Ok(config_dir.join("config.json"))
+}
+
+/// Environment variable that selects the host for `target`, such as
+/// `CARGO_XRUN_HOST_AARCH64_UNKNOWN_LINUX_GNU`.
+pub fn host_env_name(target: &str) -> String {
+    format!(
+        "CARGO_XRUN_HOST_{}",
+        target.to_uppercase().replace(['-', '.'], "_")
+    )
+}
 
-    let config_path = config_dir.join("config.json");
+/// Loads the settings for `target`, running it on `host` if given.
+///
+/// Without `host`, the host comes from `CARGO_XRUN_HOST_<TRIPLE>`, `CARGO_XRUN_HOST` or the
+/// hosts assigned to the target in the config file, in that order. If none of them has one, the
+/// user is asked to pick a host, which is then stored in the config file.
+pub fn load_for_target(target: &str, host: Option<&str>) -> anyhow::Result<TargetConfig> {
+    let host_name = host
+        .map(str::to_string)
+        .or_else(|| std::env::var(host_env_name(target)).ok())
+        .or_else(|| std::env::var("CARGO_XRUN_HOST").ok())
+        .filter(|name| !name.is_empty());
+    let (mut config, hosts) = match host_name {
+        // A host that is not configured is taken as a plain SSH destination.
+        Some(name) => {
+            let config = load()?;
+            let host = config.find_host(&name).cloned().unwrap_or(Host {
+                destination: name,
+                ..Default::default()
+            });
+            (config, vec![host])
+        }
+        None => {
+            let config = load_with_prompt(target)?;
+            let hosts = config.hosts_for(target);
+            (config, hosts)
+        }
+    };
 
-    // Create or open config file
-    let mut json_config_file = File::options()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(&config_path)
-        .with_context(|| format!("Failed to open or create config file at {:?}", &config_path))?;
+    let target_settings = config.target.remove(target).unwrap_or_default();
+    Ok(TargetConfig {
+        hosts,
+        pool_strategy: target_settings.pool_strategy,
+        env_forward: target_settings.env_forward,
+        agent: target_settings.agent,
+        exported_paths: config.exported_paths,
+        path_args: config.path_args,
+    })
+}
 
-    let mut json_config_str = String::new();
-    json_config_file
-        .read_to_string(&mut json_config_str)
-        .context(format!("Failed to read config file at {:?}", &config_path))?;
+/// Loads the config file, asking the user to pick a host for `target` if it has none yet. The
+/// config stays locked while the user picks.
+fn load_with_prompt(target: &str) -> anyhow::Result<ConfigFile> {
+    let config_path = config_path()?;
+    let _lock = store::ConfigLock::acquire(&config_path)?;
+    let original_json_config_str = store::read(&config_path)?;
 
-    let host = upsert_with(&mut json_config_str, target, |existing_hosts| {
+    let mut json_config_str = original_json_config_str.clone();
+    upsert_with(&mut json_config_str, target, |existing_hosts| {
+        if !std::io::stdin().is_terminal() {
+            anyhow::bail!(
+                "No host is configured for target {target}, and stdin is not a terminal to ask \
+                for one.\nPass --host, set {} or CARGO_XRUN_HOST, or map the target with \
+                `cargo xrun hosts add <destination> --target {target}`",
+                host_env_name(target)
+            );
+        }
         match prompt_for_host_selection(existing_hosts, target) {
             Ok(user_response) => Ok(user_response),
             Err(InquireError::OperationCanceled | InquireError::OperationInterrupted) => {
@@ -111,10 +181,46 @@ pub fn get_ssh_destination(target: &str) -> anyhow::Result<String> {
             Err(err) => Err(anyhow::Error::from(err).context("Failed during prompt")),
         }
     })?;
+    if json_config_str != original_json_config_str {
+        store::write(&config_path, &json_config_str)?;
+    }
 
-    json_config_file.set_len(0)?;
-    json_config_file.seek(SeekFrom::Start(0))?;
-    json_config_file.write_all(json_config_str.as_bytes())?;
+    config_file::parse(&json_config_str)
+}
+
+/// Reads the config file, treating a missing file as an empty config.
+pub fn load() -> anyhow::Result<ConfigFile> {
+    let config_path = config_path()?;
+    let _lock = store::ConfigLock::acquire(&config_path)?;
+    config_file::parse(&store::read(&config_path)?)
+}
 
-    Ok(host.destination)
+/// Applies `edit` to the config file, creating the file if needed, and writes the result back.
+pub fn update<T>(edit: impl FnOnce(&mut ConfigFile) -> anyhow::Result<T>) -> anyhow::Result<T> {
+    let config_path = config_path()?;
+    let _lock = store::ConfigLock::acquire(&config_path)?;
+
+    let mut config = config_file::parse(&store::read(&config_path)?)?;
+    let result = edit(&mut config)?;
+    let json_config_str =
+        serde_json::to_string_pretty(&config).context("Failed to serialize config to JSON")?;
+    store::write(&config_path, &json_config_str)?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_env_name() {
+        assert_eq!(
+            host_env_name("aarch64-unknown-linux-gnu"),
+            "CARGO_XRUN_HOST_AARCH64_UNKNOWN_LINUX_GNU"
+        );
+        assert_eq!(
+            host_env_name("thumbv7neon-linux-androideabi.2"),
+            "CARGO_XRUN_HOST_THUMBV7NEON_LINUX_ANDROIDEABI_2"
+        );
+    }
 }
@@ -0,0 +1,151 @@
+//! Reading and writing `config.json` safely while several cargo-xrun processes share it.
+//!
+//! Read-modify-write cycles hold an advisory lock on `config.json.lock`, and writes replace the
+//! file atomically. The previous contents are kept in `config.json.bak`, which stands in for a
+//! `config.json` that does not parse, e.g. after a bad manual edit.
+
+use std::{
+    ffi::OsString,
+    fs::File,
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context as _;
+
+use super::config_file;
+
+fn sibling_path(config_path: &Path, extension: &str) -> PathBuf {
+    let mut path = OsString::from(config_path);
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+/// Exclusive lock on the config file, released on drop.
+pub struct ConfigLock {
+    _file: File,
+}
+
+impl ConfigLock {
+    /// Blocks until no other cargo-xrun process holds the lock for `config_path`.
+    pub fn acquire(config_path: &Path) -> anyhow::Result<Self> {
+        if let Some(config_dir) = config_path.parent() {
+            std::fs::create_dir_all(config_dir)?;
+        }
+        let lock_path = sibling_path(config_path, "lock");
+        let file = File::options()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)
+            .with_context(|| format!("Failed to open lock file at {:?}", &lock_path))?;
+        file.lock()
+            .with_context(|| format!("Failed to lock {:?}", &lock_path))?;
+        Ok(Self { _file: file })
+    }
+}
+
+/// Reads the config file, treating a missing file as empty. Requires the [`ConfigLock`].
+///
+/// If the file does not parse but the backup does, the backup is restored, and the malformed file
+/// is set aside as `config.json.malformed`.
+pub fn read(config_path: &Path) -> anyhow::Result<String> {
+    let json_config_str = match std::fs::read_to_string(config_path) {
+        Ok(json_config_str) => json_config_str,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => {
+            return Err(err).context(format!("Failed to read config file at {:?}", config_path));
+        }
+    };
+    let Err(parse_error) = config_file::parse(&json_config_str) else {
+        return Ok(json_config_str);
+    };
+
+    let backup_path = sibling_path(config_path, "bak");
+    let backup = std::fs::read_to_string(&backup_path)
+        .ok()
+        .filter(|backup| config_file::parse(backup).is_ok());
+    let Some(backup) = backup else {
+        return Err(parse_error).context(format!(
+            "Config file at {:?} is malformed, and there is no usable backup at {:?}",
+            config_path, &backup_path
+        ));
+    };
+    let malformed_path = sibling_path(config_path, "malformed");
+    write_atomically(&malformed_path, &json_config_str)?;
+    write_atomically(config_path, &backup)?;
+    eprintln!(
+        "warning: config file at {:?} was malformed ({:#}) and has been restored from {:?}. The \
+        malformed file was moved to {:?}",
+        config_path, parse_error, &backup_path, &malformed_path
+    );
+    Ok(backup)
+}
+
+/// Replaces the config file with `json_config_str`, keeping the current contents as the backup
+/// if they parse.
+pub fn write(config_path: &Path, json_config_str: &str) -> anyhow::Result<()> {
+    if let Ok(current) = std::fs::read_to_string(config_path)
+        && config_file::parse(&current).is_ok()
+    {
+        write_atomically(&sibling_path(config_path, "bak"), &current)?;
+    }
+    write_atomically(config_path, json_config_str)
+}
+
+/// Writes `contents` to a temporary file next to `path` and renames it over `path`, so readers
+/// see either the old or the new contents.
+fn write_atomically(path: &Path, contents: &str) -> anyhow::Result<()> {
+    let dir = path
+        .parent()
+        .with_context(|| format!("{:?} has no parent directory", path))?;
+    let mut file = tempfile::NamedTempFile::new_in(dir)
+        .with_context(|| format!("Failed to create a temporary file in {:?}", dir))?;
+    file.write_all(contents.as_bytes())?;
+    file.as_file().sync_all()?;
+    file.persist(path)
+        .with_context(|| format!("Failed to write {:?}", path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_keeps_backup_and_read_recovers() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config.json");
+        let _lock = ConfigLock::acquire(&config_path).unwrap();
+
+        assert_eq!(read(&config_path).unwrap(), "");
+        write(&config_path, r#"{"host":[]}"#).unwrap();
+        write(
+            &config_path,
+            r#"{"host":[{"destination":"a","targets":[]}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("config.json.bak")).unwrap(),
+            r#"{"host":[]}"#
+        );
+
+        std::fs::write(&config_path, r#"{"host":[{"destination":"#).unwrap();
+        assert_eq!(read(&config_path).unwrap(), r#"{"host":[]}"#);
+        assert_eq!(
+            std::fs::read_to_string(&config_path).unwrap(),
+            r#"{"host":[]}"#
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("config.json.malformed")).unwrap(),
+            r#"{"host":[{"destination":"#
+        );
+
+        // Without a usable backup, a malformed file is an error.
+        write(&config_path, r#"{"host":[]}"#).unwrap();
+        std::fs::write(dir.path().join("config.json.bak"), "garbage").unwrap();
+        std::fs::write(&config_path, "garbage").unwrap();
+        assert!(read(&config_path).is_err());
+    }
+}
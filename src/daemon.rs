@@ -0,0 +1,431 @@
+//! `cargo xrun session`: a background daemon that holds the master connection to a host and the
+//! file server, so that `cargo xrun` and `cargo xtest` skip setting them up on every run.
+//!
+//! Each daemon serves one target on one host and listens on a Unix socket in the runtime
+//! directory, named after both. Requests and responses are single lines of JSON. The socket
+//! hands out the file server's token, so only the user who started the daemon can connect.
+
+use std::{
+    fs::{DirBuilder, Permissions},
+    os::unix::fs::{DirBuilderExt as _, MetadataExt as _, PermissionsExt as _},
+    path::{Path, PathBuf},
+    process::{ExitCode, Stdio},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context as _;
+use clap::{Args, Subcommand};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{
+    io::{AsyncBufReadExt as _, AsyncWriteExt as _, BufReader},
+    net::{UnixListener, UnixStream},
+    process::Command,
+};
+
+use crate::{
+    RemoteSession,
+    config::{self, Host},
+    runner::{ExecMode, RunnerConfig},
+    target::{self, TargetSpec},
+    workspace,
+};
+
+/// How long `session start` waits for the daemon to connect.
+const START_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Subcommand)]
+pub enum SessionCommand {
+    /// Start a daemon that keeps a session with a host of the target open for later runs
+    Start(SessionArgs),
+    /// Stop the target's daemon
+    Stop(SessionArgs),
+    /// Show whether the target's daemon is running, and on which host
+    Status(SessionArgs),
+    /// Run the daemon in the foreground
+    #[command(hide = true)]
+    Serve {
+        #[clap(flatten)]
+        args: SessionArgs,
+
+        /// Log file the daemon's stderr goes to, which it renames after the host once connected
+        #[clap(long)]
+        log: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct SessionArgs {
+    /// Target triple
+    #[clap(name = "target", long)]
+    triple: String,
+
+    /// Use HOST instead of the hosts the target is mapped to, as with 'cargo xrun --host'
+    #[clap(long, value_name = "HOST")]
+    host: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+enum Request {
+    Status,
+    Stop,
+    /// Status along with the runner settings to run through the daemon.
+    Attach,
+}
+
+/// What a daemon reports about itself.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DaemonInfo {
+    pub pid: u32,
+    pub target: String,
+    pub host: Host,
+    /// Seconds since the Unix epoch.
+    pub started_at: u64,
+    /// Whether the master connection is still up.
+    pub connected: bool,
+    /// Host directories the daemon's file server exports.
+    pub exported_roots: Vec<PathBuf>,
+    /// The session part of the runner settings, as in [`RemoteSession::runner_config`]. Only sent
+    /// in answer to [`Request::Attach`], since it holds the file server's token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runner_config: Option<RunnerConfig>,
+}
+
+fn sessions_dir() -> anyhow::Result<PathBuf> {
+    Ok(dirs::runtime_dir()
+        .or_else(dirs::cache_dir)
+        .context("Could not determine runtime directory")?
+        .join("cargo-xrun")
+        .join("sessions"))
+}
+
+/// Creates [`sessions_dir`], accessible only by the user even where it falls back to the
+/// world-readable cache directory.
+fn create_sessions_dir() -> anyhow::Result<PathBuf> {
+    let dir = sessions_dir()?;
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)
+        .with_context(|| format!("Failed to create {:?}", dir))?;
+    std::fs::set_permissions(&dir, Permissions::from_mode(0o700))?;
+    Ok(dir)
+}
+
+/// Name of the socket and the log of the daemon for `target` on the host at `destination`.
+fn daemon_key(target: &str, destination: &str) -> String {
+    // Hashed, since socket paths are short and target spec paths are not.
+    Sha256::digest(format!("{}\0{}", target, destination))
+        .iter()
+        .take(8)
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Socket of the daemon for `target` on the host at `destination`.
+fn socket_path(target: &str, destination: &str) -> anyhow::Result<PathBuf> {
+    Ok(sessions_dir()?.join(format!("{}.sock", daemon_key(target, destination))))
+}
+
+/// Log of the daemon for `target` on the host at `destination`.
+fn log_path(target: &str, destination: &str) -> anyhow::Result<PathBuf> {
+    Ok(sessions_dir()?.join(format!("{}.log", daemon_key(target, destination))))
+}
+
+async fn request(socket: &Path, request: &Request) -> anyhow::Result<DaemonInfo> {
+    let mut stream = UnixStream::connect(socket).await?;
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes()).await?;
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response).await?;
+    Ok(serde_json::from_str(&response)?)
+}
+
+/// The daemons running for `target` on any of `hosts`, with their sockets. Sockets left behind by
+/// daemons that are gone are removed.
+async fn running(target: &str, hosts: &[Host]) -> anyhow::Result<Vec<(PathBuf, DaemonInfo)>> {
+    let mut daemons = Vec::new();
+    for host in hosts {
+        let socket = socket_path(target, &host.destination)?;
+        if !socket.exists() {
+            continue;
+        }
+        match request(&socket, &Request::Status).await {
+            Ok(info) => daemons.push((socket, info)),
+            Err(err) => {
+                tracing::debug!("Removing stale session socket {:?}: {:?}", socket, err);
+                let _ = std::fs::remove_file(&socket);
+            }
+        }
+    }
+    Ok(daemons)
+}
+
+/// A daemon `cargo xrun` can run `target` through instead of opening a session of its own: one
+/// that is still connected and exports all of `exported_roots`.
+pub async fn find(target: &str, hosts: &[Host], exported_roots: &[PathBuf]) -> Option<DaemonInfo> {
+    let daemons = running(target, hosts).await.ok()?;
+    for (socket, info) in daemons {
+        let daemon_roots = &info.exported_roots;
+        if !info.connected {
+            eprintln!(
+                "cargo-xrun: not using the session for {} on {}: its connection is lost",
+                target,
+                info.host.name()
+            );
+        } else if let Some(root) = exported_roots
+            .iter()
+            .find(|root| !workspace::is_exported(root, daemon_roots))
+        {
+            eprintln!(
+                "cargo-xrun: not using the session for {} on {}: it does not export {:?}",
+                target,
+                info.host.name(),
+                root
+            );
+        } else if let Ok(info) = request(&socket, &Request::Attach).await
+            && info.runner_config.is_some()
+        {
+            return Some(info);
+        }
+    }
+    None
+}
+
+pub async fn run(command: SessionCommand) -> anyhow::Result<ExitCode> {
+    match command {
+        SessionCommand::Start(args) => start(args).await,
+        SessionCommand::Stop(args) => stop(args).await,
+        SessionCommand::Status(args) => status(args).await,
+        SessionCommand::Serve { args, log } => serve(args, log).await,
+    }
+}
+
+fn describe(info: &DaemonInfo) -> String {
+    let since = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs().saturating_sub(info.started_at));
+    format!(
+        "{} on {} (pid {}, up {}m{}s, {})",
+        info.target,
+        info.host.name(),
+        info.pid,
+        since / 60,
+        since % 60,
+        if info.connected {
+            "connected"
+        } else {
+            "connection lost"
+        }
+    )
+}
+
+async fn start(args: SessionArgs) -> anyhow::Result<ExitCode> {
+    let target_name = target::short_name(&args.triple);
+    let target_config = config::load_for_target(target_name, args.host.as_deref())?;
+    if let Some((_, info)) = running(target_name, &target_config.hosts).await?.first() {
+        println!("Already running: {}", describe(info));
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // Which host of a pool the daemon connects to is only known once it has, so it starts out
+    // logging to a file of its own and renames it after the host then.
+    let dir = create_sessions_dir()?;
+    let starting_log = dir.join(format!("starting-{}.log", std::process::id()));
+    let log = std::fs::File::create(&starting_log)
+        .with_context(|| format!("Failed to create {:?}", starting_log))?;
+
+    let mut command = Command::new(std::env::current_exe()?);
+    command
+        .args(["xrun", "session", "serve", "--target", &args.triple])
+        .args(args.host.iter().flat_map(|host| ["--host", host]))
+        .arg("--log")
+        .arg(&starting_log)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(log)
+        // Out of the terminal's process group, so Ctrl-C in the terminal does not reach it.
+        .process_group(0);
+    let mut daemon = command
+        .spawn()
+        .context("Failed to spawn the session daemon")?;
+
+    let deadline = tokio::time::Instant::now() + START_TIMEOUT;
+    while tokio::time::Instant::now() < deadline {
+        if let Some(status) = daemon.try_wait()? {
+            let log = std::fs::read_to_string(&starting_log).unwrap_or_default();
+            let _ = std::fs::remove_file(&starting_log);
+            anyhow::bail!(
+                "The session daemon exited with {}:\n{}",
+                status,
+                log.trim_end()
+            );
+        }
+        if let Some((_, info)) = running(target_name, &target_config.hosts).await?.first() {
+            println!(
+                "Started: {}, logging to {:?}",
+                describe(info),
+                log_path(target_name, &info.host.destination)?
+            );
+            return Ok(ExitCode::SUCCESS);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    anyhow::bail!(
+        "The session daemon did not come up within {}s; see {:?}",
+        START_TIMEOUT.as_secs(),
+        starting_log
+    )
+}
+
+async fn stop(args: SessionArgs) -> anyhow::Result<ExitCode> {
+    let target_name = target::short_name(&args.triple);
+    let target_config = config::load_for_target(target_name, args.host.as_deref())?;
+    let daemons = running(target_name, &target_config.hosts).await?;
+    if daemons.is_empty() {
+        println!("No session running for {}", target_name);
+    }
+    for (socket, info) in daemons {
+        request(&socket, &Request::Stop).await?;
+        println!("Stopped: {}", describe(&info));
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn status(args: SessionArgs) -> anyhow::Result<ExitCode> {
+    let target_name = target::short_name(&args.triple);
+    let target_config = config::load_for_target(target_name, args.host.as_deref())?;
+    let daemons = running(target_name, &target_config.hosts).await?;
+    if daemons.is_empty() {
+        println!("No session running for {}", target_name);
+        return Ok(ExitCode::FAILURE);
+    }
+    for (_, info) in &daemons {
+        println!("Running: {}", describe(info));
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// The daemon itself: opens the session as `cargo xrun` would from the current directory, then
+/// answers requests until asked to stop or interrupted.
+async fn serve(args: SessionArgs, log: Option<PathBuf>) -> anyhow::Result<ExitCode> {
+    let target_name = target::short_name(&args.triple);
+    let target_spec = TargetSpec::resolve(&args.triple).await?;
+    let target_config = config::load_for_target(target_name, args.host.as_deref())?;
+    let metadata = workspace::metadata().await?;
+    let manifest_settings = config::manifest::load_for_target(&metadata, target_name)?;
+    let exported_roots = workspace::exported_roots(
+        &metadata,
+        target_config
+            .exported_paths
+            .iter()
+            .cloned()
+            .chain(manifest_settings.exported_paths),
+    )
+    .await?;
+    let session = RemoteSession::open(
+        &target_spec,
+        &target_config,
+        exported_roots,
+        Vec::new(),
+        ExecMode::Upload.required_capabilities() | cargo_xrun_remote::protocol::CAP_LAUNCH_OPTIONS,
+    )
+    .await?;
+
+    let destination = &session.host().destination;
+    create_sessions_dir()?;
+    if let Some(log) = log {
+        std::fs::rename(&log, log_path(target_name, destination)?)?;
+    }
+    let socket = socket_path(target_name, destination)?;
+    // `session start` already made sure no daemon is listening there.
+    let _ = std::fs::remove_file(&socket);
+    let listener =
+        UnixListener::bind(&socket).with_context(|| format!("Failed to listen on {:?}", socket))?;
+    std::fs::set_permissions(&socket, Permissions::from_mode(0o600))?;
+    let owner = std::fs::metadata(&socket)?.uid();
+    let mut info = DaemonInfo {
+        pid: std::process::id(),
+        target: target_name.to_string(),
+        host: session.host().clone(),
+        started_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        connected: true,
+        exported_roots: session.runner_config().exported_roots.clone(),
+        runner_config: None,
+    };
+    tracing::info!("Serving {} on {:?}", describe(&info), socket);
+
+    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+    loop {
+        let mut stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    tracing::warn!("Failed to accept a connection: {:?}", err);
+                    continue;
+                }
+            },
+            _ = tokio::signal::ctrl_c() => break,
+            _ = sigterm.recv() => break,
+        };
+        if !stream.peer_cred().is_ok_and(|cred| cred.uid() == owner) {
+            tracing::warn!("Refusing a connection from another user");
+            continue;
+        }
+        let (reader, mut writer) = stream.split();
+        let mut line = String::new();
+        if BufReader::new(reader).read_line(&mut line).await.is_err() {
+            continue;
+        }
+        let Ok(request) = serde_json::from_str::<Request>(&line) else {
+            tracing::warn!("Ignoring malformed request {:?}", line);
+            continue;
+        };
+        info.connected = session.is_connected().await;
+        let mut response = if matches!(request, Request::Attach) {
+            serde_json::to_string(&DaemonInfo {
+                runner_config: Some(session.runner_config().clone()),
+                ..info.clone()
+            })?
+        } else {
+            serde_json::to_string(&info)?
+        };
+        response.push('\n');
+        let _ = writer.write_all(response.as_bytes()).await;
+        if matches!(request, Request::Stop) {
+            break;
+        }
+    }
+
+    let _ = std::fs::remove_file(&socket);
+    session.shutdown().await?;
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_daemon_paths() {
+        let path = socket_path("aarch64-unknown-linux-gnu", "user@pi").unwrap();
+        assert_eq!(path.extension().unwrap(), "sock");
+        assert_eq!(
+            path,
+            socket_path("aarch64-unknown-linux-gnu", "user@pi").unwrap()
+        );
+        assert_ne!(
+            path,
+            socket_path("armv7-unknown-linux-gnueabihf", "user@pi").unwrap()
+        );
+        assert_ne!(
+            path,
+            socket_path("aarch64-unknown-linux-gnu", "user@other").unwrap()
+        );
+        assert_eq!(
+            log_path("aarch64-unknown-linux-gnu", "user@pi").unwrap(),
+            path.with_extension("log")
+        );
+    }
+}
@@ -0,0 +1,403 @@
+//! `cargo xrun doctor`: checks the setup for a target step by step and explains what to fix.
+
+use std::{fmt::Display, process::ExitCode, process::Stdio};
+
+use cargo_xrun_remote::protocol;
+use clap::Args;
+use tokio::{io::AsyncWriteExt as _, process::Command};
+
+use crate::{
+    agent::RemoteAgent,
+    config::{self, Host},
+    embedded_binaries::{self, AgentBinary},
+    fs_server, pool,
+    ssh_master::{self, SshMaster},
+    target::{self, TargetOs, TargetSpec},
+    workspace,
+};
+
+#[derive(Debug, Args)]
+pub struct DoctorArgs {
+    /// Target triple to check
+    #[clap(name = "target", long)]
+    triple: String,
+
+    /// Check HOST instead of the hosts the target is mapped to, as with 'cargo xrun --host'
+    #[clap(long, value_name = "HOST")]
+    host: Option<String>,
+
+    /// Check this builder instead of the configured one
+    #[clap(long)]
+    builder: Option<String>,
+}
+
+/// Prints the result of each check as it completes.
+#[derive(Default)]
+struct Report {
+    failed: bool,
+}
+
+impl Report {
+    fn pass(&mut self, check: &str, detail: impl Display) {
+        println!("  ok    {}: {}", check, detail);
+    }
+
+    fn fail(&mut self, check: &str, error: impl Display, hint: impl Display) {
+        self.failed = true;
+        println!("  FAIL  {}: {}", check, error);
+        println!("        hint: {}", hint);
+    }
+}
+
+/// Canonical name of an architecture as reported by `uname -m` or `PROCESSOR_ARCHITECTURE`, or as
+/// in `target_arch`.
+fn normalize_arch(arch: &str) -> String {
+    let arch = arch.to_ascii_lowercase();
+    match arch.as_str() {
+        "amd64" | "x64" => "x86_64".to_string(),
+        "x86" | "i386" | "i486" | "i586" | "i686" => "x86".to_string(),
+        "arm64" => "aarch64".to_string(),
+        _ if arch.starts_with("armv") => "arm".to_string(),
+        _ if arch.starts_with("riscv64") => "riscv64".to_string(),
+        _ => arch,
+    }
+}
+
+/// Whether a machine of `machine_arch` runs executables for `target_arch`, including 32-bit
+/// executables on the matching 64-bit architecture.
+fn runs_on(target_arch: &str, machine_arch: &str) -> bool {
+    let (target_arch, machine_arch) = (normalize_arch(target_arch), normalize_arch(machine_arch));
+    target_arch == machine_arch
+        || matches!(
+            (target_arch.as_str(), machine_arch.as_str()),
+            ("x86", "x86_64") | ("arm", "aarch64")
+        )
+}
+
+pub async fn run(args: DoctorArgs) -> anyhow::Result<ExitCode> {
+    let DoctorArgs {
+        triple,
+        host,
+        builder,
+    } = args;
+    let target_name = target::short_name(&triple);
+    let target_spec = TargetSpec::resolve(&triple).await?;
+    let target_os = target_spec.target_os()?;
+    let mut report = Report::default();
+
+    // Outside a workspace there are no manifest settings, which is no reason to stop here.
+    let builder = match workspace::metadata().await {
+        Ok(metadata) => {
+            builder.or(config::manifest::load_for_target(&metadata, target_name)?.builder)
+        }
+        Err(_) => builder,
+    };
+    println!("Local:");
+    match crate::resolve_builder(builder.clone()) {
+        Ok(path) => report.pass("builder", path.display()),
+        Err(err) => report.fail(
+            "builder",
+            format!("{:#}", err),
+            format!(
+                "Install {}, or pick another builder with --builder or `builder` in \
+                [package.metadata.xrun]",
+                builder.as_deref().unwrap_or("cargo")
+            ),
+        ),
+    }
+
+    let target_config = config::load_for_target(target_name, host.as_deref())?;
+    let agent = match embedded_binaries::for_target(&target_spec, target_config.agent.as_deref()) {
+        Ok(agent) => {
+            report.pass("agent binary", &agent.file_name);
+            Some(agent)
+        }
+        Err(err) => {
+            report.fail(
+                "agent binary",
+                format!("{:#}", err),
+                "Without an agent, nothing can run on the hosts",
+            );
+            None
+        }
+    };
+
+    let fs_server_token = fs_server::generate_token();
+    let (dav_port, server_fut) =
+        fs_server::serve_webdav(&fs_server_token, Vec::new(), Vec::new(), agent.as_ref()).await?;
+    tokio::spawn(server_fut);
+
+    for host in &target_config.hosts {
+        match &host.alias {
+            Some(alias) => println!("Host {} ({}):", alias, host.destination),
+            None => println!("Host {}:", host.destination),
+        }
+        check_host(
+            &mut report,
+            host,
+            &target_spec,
+            agent.as_ref(),
+            target_os,
+            &fs_server_token,
+            dav_port,
+        )
+        .await;
+    }
+
+    Ok(if report.failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Runs the checks for one host, stopping at the first failure since later checks build on the
+/// earlier ones.
+async fn check_host(
+    report: &mut Report,
+    host: &Host,
+    target: &TargetSpec,
+    agent: Option<&AgentBinary>,
+    os: TargetOs,
+    fs_server_token: &str,
+    dav_port: u16,
+) {
+    // A plain session first, to tell connection problems from forwarding problems.
+    let ssh = Command::new("ssh")
+        .args(ssh_master::connection_args(&pool::with_connect_timeout(
+            host,
+        )))
+        .arg(&host.destination)
+        .arg("exit")
+        .stdin(Stdio::null())
+        .output()
+        .await;
+    match ssh {
+        Ok(output) if output.status.code() != Some(255) => {
+            report.pass("ssh", "connected");
+        }
+        result => {
+            let error = match result {
+                Ok(output) => String::from_utf8_lossy(&output.stderr).trim().to_string(),
+                Err(err) => format!("Failed to spawn ssh: {}", err),
+            };
+            report.fail(
+                "ssh",
+                error,
+                format!(
+                    "Check that `ssh {}` logs in from this machine, and the host's port, \
+                    identity_file and proxy_jump settings",
+                    host.destination
+                ),
+            );
+            return;
+        }
+    }
+
+    let ssh_master = match SshMaster::start(host, dav_port, os).await {
+        Ok(ssh_master) => {
+            report.pass(
+                "port forwarding",
+                format!("remote port {}", ssh_master.remote_port()),
+            );
+            ssh_master
+        }
+        Err(err) => {
+            report.fail(
+                "port forwarding",
+                format!("{:#}", err),
+                "The SSH server must allow remote port forwarding: set `AllowTcpForwarding yes` \
+                in its sshd_config",
+            );
+            return;
+        }
+    };
+
+    check_agent(
+        report,
+        &ssh_master,
+        host,
+        target,
+        agent,
+        os,
+        fs_server_token,
+        dav_port,
+    )
+    .await;
+    if let Err(err) = ssh_master.stop().await {
+        tracing::warn!("Failed to stop ssh master: {:?}", err);
+    }
+}
+
+/// Runs the checks that go through the master connection of [`check_host`], which stops the
+/// master whichever check fails.
+#[allow(clippy::too_many_arguments)]
+async fn check_agent(
+    report: &mut Report,
+    ssh_master: &SshMaster,
+    host: &Host,
+    target: &TargetSpec,
+    agent: Option<&AgentBinary>,
+    os: TargetOs,
+    fs_server_token: &str,
+    dav_port: u16,
+) {
+    // Already reported as missing.
+    let Some(agent) = agent else {
+        return;
+    };
+    let agent = match RemoteAgent::prepare(ssh_master, agent, os, host.remote_dir.as_deref()).await
+    {
+        Ok(agent) => agent,
+        Err(err) => {
+            let hint = match os {
+                TargetOs::Windows => {
+                    "The agent installs itself from the file server share, which needs the \
+                    WebClient service on the remote; check it with `sc query WebClient`"
+                }
+                TargetOs::Linux => {
+                    "The agent is installed in the host's remote_dir or ~/.cache/cargo-xrun, which \
+                    must be writable and not mounted noexec"
+                }
+            };
+            report.fail("agent", format!("{:#}", err), hint);
+            return;
+        }
+    };
+
+    match agent
+        .check_version(ssh_master, os, protocol::CAP_DOCTOR)
+        .await
+    {
+        Ok(header) => report.pass(
+            "protocol",
+            format!(
+                "version {} with {}",
+                header.version,
+                protocol::capability_names(header.capabilities)
+            ),
+        ),
+        Err(err) => {
+            report.fail(
+                "protocol",
+                format!("{:#}", err),
+                "Remove `agent` from the target in the config file to use the embedded agent, or \
+                rebuild that agent from the same version of cargo-xrun",
+            );
+            return;
+        }
+    }
+
+    let run_id = fs_server::generate_token();
+    let outcome_path = format!("{}/outcome/{}", fs_server_token, run_id);
+    // The URL carries the session token, so it goes over stdin rather than on the command line.
+    let outcome_url = format!(
+        "http://localhost:{}/{}",
+        ssh_master.remote_port(),
+        outcome_path
+    );
+    let output = async {
+        let mut child = ssh_master
+            .command()
+            .arg(os.quote_arg(agent.path()))
+            .arg("doctor")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(outcome_url.as_bytes()).await?;
+        drop(stdin);
+        child.wait_with_output().await
+    }
+    .await;
+    let lines = match &output {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .map(|(key, value)| (key.to_string(), value.trim().to_string()))
+            .collect::<Vec<_>>(),
+        _ => Vec::new(),
+    };
+    let value = |key: &str| {
+        lines
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    };
+    let Some(machine_arch) = value("arch") else {
+        let error = match output {
+            Ok(output) => format!(
+                "{} exited with {} {}",
+                agent.path(),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            Err(err) => format!("Failed to spawn ssh: {}", err),
+        };
+        report.fail(
+            "agent",
+            error,
+            format!(
+                "The agent for {} on {} does not run on this host; check that the target matches \
+                the host's architecture and OS",
+                target.arch, target.os
+            ),
+        );
+        return;
+    };
+    report.pass("agent", agent.path());
+
+    let target_arch = &target.arch;
+    if runs_on(target_arch, machine_arch) {
+        report.pass("architecture", machine_arch);
+    } else {
+        report.fail(
+            "architecture",
+            format!("{} executables do not run on {}", target_arch, machine_arch),
+            format!("Pick a --target for {}, or another host", machine_arch),
+        );
+    }
+
+    // The agent PUTs through the forwarded port, and the check passes once the host sees it.
+    let outcome_url = format!("http://localhost:{}/{}", dav_port, outcome_path);
+    let round_trip = tokio::task::spawn_blocking(move || {
+        let mut body = Vec::new();
+        cargo_xrun_remote::http::get(&outcome_url, &mut body).ok()?;
+        Some(body)
+    })
+    .await
+    .ok()
+    .flatten();
+    match (value("tunnel"), round_trip) {
+        (Some("ok"), Some(body)) if body == b"ping" => {
+            report.pass("file server tunnel", "round trip completed");
+        }
+        (tunnel, _) => report.fail(
+            "file server tunnel",
+            tunnel.unwrap_or("the request never reached the host"),
+            format!(
+                "Check that nothing on the remote blocks connections to localhost:{}, such as a \
+                firewall",
+                ssh_master.remote_port()
+            ),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runs_on() {
+        assert!(runs_on("x86_64", "x86_64"));
+        assert!(runs_on("x86_64", "AMD64"));
+        assert!(runs_on("i686", "AMD64"));
+        assert!(runs_on("aarch64", "ARM64"));
+        assert!(runs_on("armv7", "armv7l"));
+        assert!(runs_on("riscv64gc", "riscv64"));
+        assert!(!runs_on("aarch64", "x86_64"));
+        assert!(!runs_on("x86_64", "i686"));
+    }
+}
@@ -1,6 +1,248 @@
-pub const WINDOWS_I686: &[u8] =
-    include_bytes!(env!("CARGO_BIN_FILE_CARGO_XRUN_REMOTE_I686_PC_WINDOWS_GNULLVM"));
-pub const LINUX_X86_64: &[u8] =
-    include_bytes!(env!("CARGO_BIN_FILE_CARGO_XRUN_REMOTE_X86_64_UNKNOWN_LINUX_MUSL"));
-pub const LINUX_AARCH64: &[u8] =
-    include_bytes!(env!("CARGO_BIN_FILE_CARGO_XRUN_REMOTE_AARCH64_UNKNOWN_LINUX_MUSL"));
+use std::path::Path;
+
+use anyhow::Context as _;
+use bytes::Bytes;
+
+use crate::target::TargetSpec;
+
+/// An agent cargo-xrun knows how to build. Only those enabled by their `agent-<triple>` feature
+/// are embedded.
+pub struct Agent {
+    pub triple: &'static str,
+    pub file_name: &'static str,
+    pub bytes: Option<&'static [u8]>,
+    /// `target_os` of the machines the agent runs on.
+    pub os: &'static str,
+    /// `target_arch` of the executables the agent can start: its own, and those of machines that
+    /// also run it, such as 64-bit Windows running the 32-bit agent.
+    pub arches: &'static [&'static str],
+}
+
+pub static WINDOWS_I686: Agent = Agent {
+    triple: "i686-pc-windows-gnullvm",
+    file_name: "cargo-xrun-remote-i686-pc-windows-gnullvm.exe",
+    #[cfg(feature = "agent-i686-pc-windows-gnullvm")]
+    bytes: Some(include_bytes!(env!(
+        "CARGO_BIN_FILE_CARGO_XRUN_REMOTE_I686_PC_WINDOWS_GNULLVM"
+    ))),
+    #[cfg(not(feature = "agent-i686-pc-windows-gnullvm"))]
+    bytes: None,
+    os: "windows",
+    // 64-bit Windows runs x86 executables, and Windows on Arm emulates x86.
+    arches: &["x86", "x86_64", "aarch64"],
+};
+pub static WINDOWS_X86_64: Agent = Agent {
+    triple: "x86_64-pc-windows-gnullvm",
+    file_name: "cargo-xrun-remote-x86_64-pc-windows-gnullvm.exe",
+    #[cfg(feature = "agent-x86_64-pc-windows-gnullvm")]
+    bytes: Some(include_bytes!(env!(
+        "CARGO_BIN_FILE_CARGO_XRUN_REMOTE_X86_64_PC_WINDOWS_GNULLVM"
+    ))),
+    #[cfg(not(feature = "agent-x86_64-pc-windows-gnullvm"))]
+    bytes: None,
+    os: "windows",
+    arches: &["x86_64"],
+};
+pub static LINUX_X86_64: Agent = Agent {
+    triple: "x86_64-unknown-linux-musl",
+    file_name: "cargo-xrun-remote-x86_64-unknown-linux-musl",
+    #[cfg(feature = "agent-x86_64-unknown-linux-musl")]
+    bytes: Some(include_bytes!(env!(
+        "CARGO_BIN_FILE_CARGO_XRUN_REMOTE_X86_64_UNKNOWN_LINUX_MUSL"
+    ))),
+    #[cfg(not(feature = "agent-x86_64-unknown-linux-musl"))]
+    bytes: None,
+    os: "linux",
+    arches: &["x86_64"],
+};
+pub static LINUX_AARCH64: Agent = Agent {
+    triple: "aarch64-unknown-linux-musl",
+    file_name: "cargo-xrun-remote-aarch64-unknown-linux-musl",
+    #[cfg(feature = "agent-aarch64-unknown-linux-musl")]
+    bytes: Some(include_bytes!(env!(
+        "CARGO_BIN_FILE_CARGO_XRUN_REMOTE_AARCH64_UNKNOWN_LINUX_MUSL"
+    ))),
+    #[cfg(not(feature = "agent-aarch64-unknown-linux-musl"))]
+    bytes: None,
+    os: "linux",
+    arches: &["aarch64"],
+};
+pub static LINUX_I686: Agent = Agent {
+    triple: "i686-unknown-linux-musl",
+    file_name: "cargo-xrun-remote-i686-unknown-linux-musl",
+    #[cfg(feature = "agent-i686-unknown-linux-musl")]
+    bytes: Some(include_bytes!(env!(
+        "CARGO_BIN_FILE_CARGO_XRUN_REMOTE_I686_UNKNOWN_LINUX_MUSL"
+    ))),
+    #[cfg(not(feature = "agent-i686-unknown-linux-musl"))]
+    bytes: None,
+    os: "linux",
+    arches: &["x86"],
+};
+pub static LINUX_ARMV7: Agent = Agent {
+    triple: "armv7-unknown-linux-musleabihf",
+    file_name: "cargo-xrun-remote-armv7-unknown-linux-musleabihf",
+    #[cfg(feature = "agent-armv7-unknown-linux-musleabihf")]
+    bytes: Some(include_bytes!(env!(
+        "CARGO_BIN_FILE_CARGO_XRUN_REMOTE_ARMV7_UNKNOWN_LINUX_MUSLEABIHF"
+    ))),
+    #[cfg(not(feature = "agent-armv7-unknown-linux-musleabihf"))]
+    bytes: None,
+    os: "linux",
+    arches: &["arm"],
+};
+pub static LINUX_RISCV64: Agent = Agent {
+    triple: "riscv64gc-unknown-linux-musl",
+    file_name: "cargo-xrun-remote-riscv64gc-unknown-linux-musl",
+    #[cfg(feature = "agent-riscv64gc-unknown-linux-musl")]
+    bytes: Some(include_bytes!(env!(
+        "CARGO_BIN_FILE_CARGO_XRUN_REMOTE_RISCV64GC_UNKNOWN_LINUX_MUSL"
+    ))),
+    #[cfg(not(feature = "agent-riscv64gc-unknown-linux-musl"))]
+    bytes: None,
+    os: "linux",
+    arches: &["riscv64"],
+};
+
+/// In order of preference: [`for_target`] picks the first embedded agent that fits.
+pub static ALL: &[&Agent] = &[
+    &WINDOWS_X86_64,
+    &WINDOWS_I686,
+    &LINUX_X86_64,
+    &LINUX_AARCH64,
+    &LINUX_I686,
+    &LINUX_ARMV7,
+    &LINUX_RISCV64,
+];
+
+/// The agent executable installed on the remote for a session.
+pub struct AgentBinary {
+    pub file_name: String,
+    pub bytes: Bytes,
+}
+
+impl AgentBinary {
+    /// Reads an agent the user built, for targets whose agent is not embedded.
+    fn from_path(path: &Path) -> anyhow::Result<Self> {
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .with_context(|| format!("Invalid agent file name: {:?}", path))?;
+        let bytes =
+            std::fs::read(path).with_context(|| format!("Failed to read agent {:?}", path))?;
+        Ok(Self {
+            file_name: file_name.to_string(),
+            bytes: bytes.into(),
+        })
+    }
+}
+
+/// The agent that starts executables for `target`: the one at `agent_path` if given, otherwise
+/// the first embedded agent that fits. The target's `env` does not matter: the agents are
+/// statically linked and run alongside any C library.
+pub fn for_target(target: &TargetSpec, agent_path: Option<&Path>) -> anyhow::Result<AgentBinary> {
+    if let Some(agent_path) = agent_path {
+        return AgentBinary::from_path(agent_path);
+    }
+
+    let mut fitting = ALL
+        .iter()
+        .filter(|agent| agent.os == target.os && agent.arches.contains(&target.arch.as_str()))
+        .peekable();
+    let Some(first) = fitting.peek().copied() else {
+        anyhow::bail!(
+            "No agent for arch {} on os {}. Agents are available for: {}. Set `agent` for the \
+            target in the config file to use an agent built for another platform",
+            target.arch,
+            target.os,
+            ALL.iter()
+                .flat_map(|agent| agent
+                    .arches
+                    .iter()
+                    .map(|arch| format!("{} on {}", arch, agent.os)))
+                .collect::<std::collections::BTreeSet<_>>()
+                .into_iter()
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    };
+    match fitting.find_map(|agent| Some((agent, agent.bytes?))) {
+        Some((agent, bytes)) => Ok(AgentBinary {
+            file_name: agent.file_name.to_string(),
+            bytes: Bytes::from_static(bytes),
+        }),
+        None => anyhow::bail!(
+            "The agent for arch {} on os {} is not embedded in this build of cargo-xrun. Rebuild it \
+            with the `agent-{}` feature, or set `agent` for the target in the config file to the \
+            path of an agent built for {}",
+            target.arch,
+            target.os,
+            first.triple,
+            first.triple
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_target() {
+        let spec = |arch: &str, os: &str| TargetSpec {
+            arch: arch.into(),
+            os: os.into(),
+            env: String::new(),
+        };
+        let file_name =
+            |arch: &str, os: &str| for_target(&spec(arch, os), None).map(|agent| agent.file_name);
+        if cfg!(feature = "agent-x86_64-pc-windows-gnullvm") {
+            assert_eq!(
+                file_name("x86_64", "windows").unwrap(),
+                WINDOWS_X86_64.file_name
+            );
+        }
+        if cfg!(feature = "agent-i686-pc-windows-gnullvm") {
+            assert_eq!(
+                file_name("aarch64", "windows").unwrap(),
+                WINDOWS_I686.file_name
+            );
+            // Without the x86_64 agent, 64-bit Windows runs the 32-bit one.
+            if !cfg!(feature = "agent-x86_64-pc-windows-gnullvm") {
+                assert_eq!(
+                    file_name("x86_64", "windows").unwrap(),
+                    WINDOWS_I686.file_name
+                );
+            }
+        }
+        if cfg!(feature = "agent-aarch64-unknown-linux-musl") {
+            assert_eq!(
+                file_name("aarch64", "linux").unwrap(),
+                LINUX_AARCH64.file_name
+            );
+        } else {
+            let err = file_name("aarch64", "linux").unwrap_err().to_string();
+            assert!(
+                err.starts_with(
+                    "The agent for arch aarch64 on os linux is not embedded in this build of \
+                    cargo-xrun. Rebuild it with the `agent-aarch64-unknown-linux-musl` feature"
+                ),
+                "{}",
+                err
+            );
+        }
+        let err = file_name("mips", "linux").unwrap_err().to_string();
+        assert!(
+            err.starts_with("No agent for arch mips on os linux."),
+            "{}",
+            err
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let agent_path = dir.path().join("agent-mips");
+        std::fs::write(&agent_path, "agent").unwrap();
+        let agent = for_target(&spec("mips", "linux"), Some(&agent_path)).unwrap();
+        assert_eq!(agent.file_name, "agent-mips");
+        assert_eq!(agent.bytes, "agent");
+    }
+}
@@ -1,4 +1,7 @@
+use std::collections::HashMap;
 use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 use bytes::Bytes;
 use dav_server::body::Body;
@@ -7,33 +10,20 @@ use dav_server::fs::{DavFileSystem, OpenOptions};
 use dav_server::localfs::LocalFs;
 use dav_server::memfs::MemFs;
 use dav_server::{DavMethodSet, memls::MemLs};
-use hyper::{Request, Response, StatusCode};
+use http_body_util::BodyExt as _;
+use hyper::{Method, Request, Response, StatusCode};
 use hyper::{server::conn::http1, service::service_fn};
 use hyper_util::rt::TokioIo;
 use tokio::net::TcpListener;
 
-use crate::embedded_binaries;
+use crate::{embedded_binaries::AgentBinary, workspace};
 
-async fn create_remote_bin_fs() -> Box<MemFs> {
+async fn create_remote_bin_fs(agent: Option<&AgentBinary>) -> Box<MemFs> {
     let fs = MemFs::new();
 
-    let files: &[(&str, &[u8])] = &[
-        (
-            "/cargo-xrun-remote-i686-pc-windows-gnullvm.exe",
-            embedded_binaries::WINDOWS_I686,
-        ),
-        (
-            "/cargo-xrun-remote-x86_64-unknown-linux-musl",
-            embedded_binaries::LINUX_X86_64,
-        ),
-        (
-            "/cargo-xrun-remote-aarch64-unknown-linux-musl",
-            embedded_binaries::LINUX_AARCH64,
-        ),
-    ];
-
-    for (path, data) in files {
-        let dav_path = DavPath::new(path).unwrap();
+    if let Some(agent) = agent {
+        let data = agent.bytes.clone();
+        let dav_path = DavPath::new(&format!("/{}", agent.file_name)).unwrap();
         let options = OpenOptions {
             read: false,
             write: true,
@@ -45,28 +35,323 @@ async fn create_remote_bin_fs() -> Box<MemFs> {
             checksum: None,
         };
         let mut file = fs.open(&dav_path, options).await.unwrap();
-        file.write_bytes(Bytes::from_static(data)).await.unwrap();
+        file.write_bytes(data).await.unwrap();
     }
 
     fs
 }
 
-pub async fn serve_webdav() -> anyhow::Result<(u16, impl Future<Output = anyhow::Error>)> {
+/// Generates the random token that prefixes every path the WebDAV server answers, so that other
+/// users of the remote machine cannot use the forwarded port.
+pub fn generate_token() -> String {
+    rand::random::<[u8; 16]>()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Maps a URL path under `fs_prefix` to the host path [`LocalFs`] serves for it.
+fn host_path(url_path: &str, fs_prefix: &str) -> Option<PathBuf> {
+    let dav_path = DavPath::new(url_path.strip_prefix(fs_prefix)?).ok()?;
+    Some(Path::new("/").join(dav_path.as_rel_ospath()))
+}
+
+/// Resolves the symlinks in `path` as [`LocalFs`] follows them. The parts of `path` that do not
+/// exist yet, such as a file about to be created, are kept as they are.
+#[cfg(unix)]
+fn resolve_symlinks(path: &Path) -> PathBuf {
+    let mut missing = Vec::new();
+    let mut existing = path;
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            return missing
+                .iter()
+                .rev()
+                .fold(canonical, |path, name| path.join(name));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name);
+                existing = parent;
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
+/// On Windows, paths are checked as they are: canonical paths there use the `\\?\` form, which
+/// the roots never match.
+#[cfg(not(unix))]
+fn resolve_symlinks(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+/// Whether a request to `fs_prefix` stays within what the remote may access. Paths are checked
+/// after resolving symlinks, so a link cannot lead out of the roots.
+///
+/// Reads are limited to `exported_roots`. Directories above a root only answer `OPTIONS` and
+/// `PROPFIND` with `Depth: 0`, which is what WebClient needs to walk down to the root without
+/// listing anything else. Writes are limited to `writable_dirs`.
+fn is_allowed<B>(
+    req: &Request<B>,
+    fs_prefix: &str,
+    exported_roots: &[PathBuf],
+    writable_dirs: &[PathBuf],
+) -> bool {
+    let Some(path) = host_path(req.uri().path(), fs_prefix) else {
+        return false;
+    };
+
+    let is_propfind = req.method().as_str() == "PROPFIND";
+    if is_propfind || matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        if workspace::is_exported(&resolve_symlinks(&path), exported_roots) {
+            return true;
+        }
+        let is_ancestor = exported_roots.iter().any(|root| root.starts_with(&path));
+        let is_shallow = *req.method() == Method::OPTIONS
+            || (is_propfind && req.headers().get("Depth").is_some_and(|depth| depth == "0"));
+        return is_ancestor && is_shallow;
+    }
+
+    if !workspace::is_exported(&resolve_symlinks(&path), writable_dirs) {
+        return false;
+    }
+    // COPY and MOVE also write to their destination
+    match req.headers().get("Destination") {
+        Some(destination) => destination
+            .to_str()
+            .ok()
+            .and_then(|destination| destination.parse::<hyper::Uri>().ok())
+            .and_then(|destination| host_path(destination.path(), fs_prefix))
+            .is_some_and(|destination| {
+                workspace::is_exported(&resolve_symlinks(&destination), writable_dirs)
+            }),
+        None => true,
+    }
+}
+
+/// Most files and bytes a `/<token>/tree` listing may add up to, so that running from a large
+/// directory fails instead of copying all of it to the remote.
+const MAX_TREE_FILES: usize = 10_000;
+const MAX_TREE_BYTES: u64 = 512 << 20;
+
+/// Appends the [`list_tree`] line of the file at `path` with `metadata` to `listing`.
+fn list_file(listing: &mut String, metadata: &std::fs::Metadata, path: &str) {
+    use std::fmt::Write as _;
+
+    #[cfg(unix)]
+    let executable = std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()) & 0o111 != 0;
+    #[cfg(not(unix))]
+    let executable = false;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |modified| modified.as_nanos());
+    writeln!(
+        listing,
+        "{} {} {} {}",
+        if executable { "x" } else { "f" },
+        metadata.len(),
+        modified,
+        path
+    )
+    .unwrap();
+}
+
+/// Lists what the Linux agent mirrors of `root`, one entry per line as
+/// `<kind> <size> <modified> <path>`: kind `d` for directories, `f` for files, `x` for executables
+/// and `s` for directories that are left out, the size in bytes, the modification time in
+/// nanoseconds since the Unix epoch, and the path relative to `root`. A file is listed as a
+/// single line with an empty path.
+///
+/// Target directories, which cargo marks with `CACHEDIR.TAG`, and `.git` are left out. Symlinks
+/// are listed as what they point to if that is a file inside `exported_roots`, and skipped
+/// otherwise.
+fn list_tree(root: &Path, exported_roots: &[PathBuf]) -> std::io::Result<String> {
+    use std::fmt::Write as _;
+
+    let mut listing = String::new();
+    let metadata = std::fs::metadata(root)?;
+    if metadata.is_file() {
+        list_file(&mut listing, &metadata, "");
+        return Ok(listing);
+    }
+    let (mut files, mut bytes) = (0, 0);
+    let mut pending = vec![(root.to_path_buf(), String::new())];
+    while let Some((dir, prefix)) = pending.pop() {
+        let mut entries = std::fs::read_dir(&dir)
+            .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+            .map_err(|err| std::io::Error::new(err.kind(), format!("{:?}: {}", dir, err)))?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            if name.contains('\n') {
+                continue;
+            }
+            let path = format!("{}{}", prefix, name);
+            let Ok(mut metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_symlink() {
+                let target = resolve_symlinks(&entry.path());
+                match std::fs::metadata(&target) {
+                    Ok(target_metadata)
+                        if target_metadata.is_file()
+                            && workspace::is_exported(&target, exported_roots) =>
+                    {
+                        metadata = target_metadata
+                    }
+                    _ => continue,
+                }
+            }
+
+            if metadata.is_dir() {
+                if name == ".git" || entry.path().join("CACHEDIR.TAG").is_file() {
+                    writeln!(listing, "s 0 0 {}", path).unwrap();
+                } else {
+                    writeln!(listing, "d 0 0 {}", path).unwrap();
+                    pending.push((entry.path(), format!("{}/", path)));
+                }
+                continue;
+            }
+            files += 1;
+            bytes += metadata.len();
+            if files > MAX_TREE_FILES || bytes > MAX_TREE_BYTES {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::FileTooLarge,
+                    format!(
+                        "{:?} holds more than {} files or {} MiB outside of target directories, \
+                        which is too much to copy to the remote. Run from a smaller directory",
+                        root,
+                        MAX_TREE_FILES,
+                        MAX_TREE_BYTES >> 20
+                    ),
+                ));
+            }
+            list_file(&mut listing, &metadata, &path);
+        }
+    }
+    Ok(listing)
+}
+
+/// Answers `GET /<token>/tree/<path>` with the [`list_tree`] listing of `path`.
+async fn handle_tree(
+    req: &Request<hyper::body::Incoming>,
+    tree_prefix: &str,
+    exported_roots: &Arc<Vec<PathBuf>>,
+) -> Response<Body> {
+    let response = |status, body: String| {
+        Response::builder()
+            .status(status)
+            .body(Body::from(body))
+            .unwrap()
+    };
+    if *req.method() != Method::GET {
+        return response(StatusCode::METHOD_NOT_ALLOWED, String::new());
+    }
+    let Some(dir) = host_path(req.uri().path(), tree_prefix)
+        .map(|dir| resolve_symlinks(&dir))
+        .filter(|dir| workspace::is_exported(dir, exported_roots))
+    else {
+        return response(StatusCode::FORBIDDEN, "Forbidden".into());
+    };
+    let exported_roots = exported_roots.clone();
+    match tokio::task::spawn_blocking(move || list_tree(&dir, &exported_roots)).await {
+        Ok(Ok(listing)) => response(StatusCode::OK, listing),
+        Ok(Err(err)) => {
+            let status = match err.kind() {
+                std::io::ErrorKind::FileTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::NotADirectory => {
+                    StatusCode::NOT_FOUND
+                }
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            response(status, err.to_string())
+        }
+        Err(err) => response(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    }
+}
+
+/// Outcomes reported by agents, keyed by run id, until the runner of that run collects them.
+type Outcomes = Arc<Mutex<HashMap<String, Bytes>>>;
+
+/// Stores (`PUT`) or collects (`GET`) the outcome of the run `run_id`.
+async fn handle_outcome(
+    req: Request<hyper::body::Incoming>,
+    run_id: &str,
+    outcomes: &Outcomes,
+) -> Response<Body> {
+    let response = |status, body: Bytes| {
+        Response::builder()
+            .status(status)
+            .body(Body::from(body))
+            .unwrap()
+    };
+    match *req.method() {
+        Method::PUT => match req.into_body().collect().await {
+            Ok(body) => {
+                let body = body.to_bytes();
+                if body.len() > 64 {
+                    return response(StatusCode::PAYLOAD_TOO_LARGE, Bytes::new());
+                }
+                outcomes.lock().unwrap().insert(run_id.to_string(), body);
+                response(StatusCode::OK, Bytes::new())
+            }
+            Err(_) => response(StatusCode::BAD_REQUEST, Bytes::new()),
+        },
+        Method::GET => match outcomes.lock().unwrap().remove(run_id) {
+            Some(outcome) => response(StatusCode::OK, outcome),
+            None => response(StatusCode::NOT_FOUND, Bytes::new()),
+        },
+        _ => response(StatusCode::METHOD_NOT_ALLOWED, Bytes::new()),
+    }
+}
+
+/// Serves the exported host filesystem under `/<token>/fs`, listings of its directories for the
+/// Linux agent under `/<token>/tree`, exit outcomes of remote processes under
+/// `/<token>/outcome/<run id>` and the session's agent under `/remote-bin`. The agent is
+/// served without the token, so Windows remotes can start it before it knows the token. Other
+/// requests without the token are refused with 403.
+///
+/// `/fs` only exposes `exported_roots`, and is read-only unless `writable_dirs` is non-empty, in
+/// which case write requests are accepted for paths inside those directories. Everything else is
+/// refused with 403.
+pub async fn serve_webdav(
+    token: &str,
+    exported_roots: Vec<PathBuf>,
+    writable_dirs: Vec<PathBuf>,
+    agent: Option<&AgentBinary>,
+) -> anyhow::Result<(u16, impl Future<Output = anyhow::Error> + use<>)> {
     let listener = TcpListener::bind("localhost:0").await?;
     let port = listener.local_addr()?.port();
 
+    let fs_prefix: Arc<str> = format!("/{}/fs", token).into();
+    let remote_bin_prefix: Arc<str> = "/remote-bin".into();
+    let tree_prefix: Arc<str> = format!("/{}/tree", token).into();
+    let outcome_prefix: Arc<str> = format!("/{}/outcome/", token).into();
+    let outcomes = Outcomes::default();
+
     let fs_handler = dav_server::DavHandler::builder()
         .filesystem(LocalFs::new("/", false, false, false))
         .locksystem(MemLs::new())
-        .methods(DavMethodSet::WEBDAV_RO)
-        .strip_prefix("/fs")
+        .methods(if writable_dirs.is_empty() {
+            DavMethodSet::WEBDAV_RO
+        } else {
+            DavMethodSet::WEBDAV_RW
+        })
+        .strip_prefix(&*fs_prefix)
         .build_handler();
+    let exported_roots = Arc::new(exported_roots);
+    let writable_dirs = Arc::new(writable_dirs);
 
-    let remote_bin_fs = create_remote_bin_fs().await;
+    let remote_bin_fs = create_remote_bin_fs(agent).await;
     let remote_bin_handler = dav_server::DavHandler::builder()
         .filesystem(remote_bin_fs)
         .methods(DavMethodSet::WEBDAV_RO)
-        .strip_prefix("/remote-bin")
+        .strip_prefix(&*remote_bin_prefix)
         .build_handler();
 
     let server_fut = async move {
@@ -79,6 +364,13 @@ pub async fn serve_webdav() -> anyhow::Result<(u16, impl Future<Output = anyhow:
             let io = TokioIo::new(stream);
             let fs_handler = fs_handler.clone();
             let remote_bin_handler = remote_bin_handler.clone();
+            let fs_prefix = fs_prefix.clone();
+            let remote_bin_prefix = remote_bin_prefix.clone();
+            let tree_prefix = tree_prefix.clone();
+            let outcome_prefix = outcome_prefix.clone();
+            let outcomes = outcomes.clone();
+            let exported_roots = exported_roots.clone();
+            let writable_dirs = writable_dirs.clone();
 
             tokio::task::spawn(async move {
                 if let Err(err) = http1::Builder::new()
@@ -88,22 +380,54 @@ pub async fn serve_webdav() -> anyhow::Result<(u16, impl Future<Output = anyhow:
                             move |req: Request<hyper::body::Incoming>| {
                                 let fs_handler = fs_handler.clone();
                                 let remote_bin_handler = remote_bin_handler.clone();
+                                let fs_prefix = fs_prefix.clone();
+                                let remote_bin_prefix = remote_bin_prefix.clone();
+                                let tree_prefix = tree_prefix.clone();
+                                let outcome_prefix = outcome_prefix.clone();
+                                let outcomes = outcomes.clone();
+                                let exported_roots = exported_roots.clone();
+                                let writable_dirs = writable_dirs.clone();
                                 async move {
                                     let path = req.uri().path();
 
-                                    if path.starts_with("/remote-bin") {
+                                    if let Some(run_id) = path.strip_prefix(&*outcome_prefix) {
+                                        let run_id = run_id.to_string();
+                                        return Ok(handle_outcome(req, &run_id, &outcomes).await);
+                                    }
+
+                                    if path.starts_with(&*tree_prefix) {
+                                        return Ok(handle_tree(
+                                            &req,
+                                            &tree_prefix,
+                                            &exported_roots,
+                                        )
+                                        .await);
+                                    }
+
+                                    if path.starts_with(&*remote_bin_prefix) {
                                         return Ok::<_, Infallible>(
                                             remote_bin_handler.handle(req).await,
                                         );
                                     }
 
-                                    if path.starts_with("/fs") {
+                                    if path.starts_with(&*fs_prefix) {
+                                        if !is_allowed(
+                                            &req,
+                                            &fs_prefix,
+                                            &exported_roots,
+                                            &writable_dirs,
+                                        ) {
+                                            return Ok(Response::builder()
+                                                .status(StatusCode::FORBIDDEN)
+                                                .body(Body::from("Forbidden"))
+                                                .unwrap());
+                                        }
                                         return Ok(fs_handler.handle(req).await);
                                     }
 
                                     Ok(Response::builder()
-                                        .status(StatusCode::NOT_FOUND)
-                                        .body(Body::from("Not Found"))
+                                        .status(StatusCode::FORBIDDEN)
+                                        .body(Body::from("Forbidden"))
                                         .unwrap())
                                 }
                             }
@@ -118,3 +442,260 @@ pub async fn serve_webdav() -> anyhow::Result<(u16, impl Future<Output = anyhow:
     };
     Ok((port, server_fut))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, path: &str, headers: &[(&str, &str)]) -> Request<()> {
+        let mut builder = Request::builder().method(method).uri(path);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn test_host_path() {
+        assert_eq!(
+            host_path("/fs/home/user/My%20Project", "/fs"),
+            Some(PathBuf::from("/home/user/My Project"))
+        );
+        assert_eq!(
+            host_path("/fs/home/user/../../etc", "/fs"),
+            Some(PathBuf::from("/etc"))
+        );
+        assert_eq!(host_path("/remote-bin/agent", "/fs"), None);
+    }
+
+    #[test]
+    fn test_is_allowed_reads() {
+        let exported_roots = [PathBuf::from("/work"), PathBuf::from("/home/user/.cargo")];
+
+        assert!(is_allowed(
+            &request("GET", "/fs/work/src/main.rs", &[]),
+            "/fs",
+            &exported_roots,
+            &[]
+        ));
+        assert!(!is_allowed(
+            &request("GET", "/fs/home/user/.ssh/id_ed25519", &[]),
+            "/fs",
+            &exported_roots,
+            &[]
+        ));
+        assert!(!is_allowed(
+            &request("GET", "/fs/work/../home/user/.ssh/id_ed25519", &[]),
+            "/fs",
+            &exported_roots,
+            &[]
+        ));
+        // Ancestors of a root can be walked through, but not listed
+        assert!(is_allowed(
+            &request("PROPFIND", "/fs/home/user", &[("Depth", "0")]),
+            "/fs",
+            &exported_roots,
+            &[]
+        ));
+        assert!(!is_allowed(
+            &request("PROPFIND", "/fs/home/user", &[("Depth", "1")]),
+            "/fs",
+            &exported_roots,
+            &[]
+        ));
+        assert!(!is_allowed(
+            &request("GET", "/fs/home/user", &[]),
+            "/fs",
+            &exported_roots,
+            &[]
+        ));
+    }
+
+    #[test]
+    fn test_is_allowed_writes() {
+        let exported_roots = [PathBuf::from("/work")];
+        let writable_dirs = [PathBuf::from("/work/target")];
+
+        assert!(is_allowed(
+            &request("PUT", "/fs/work/target/tmp/out.txt", &[]),
+            "/fs",
+            &exported_roots,
+            &writable_dirs
+        ));
+        assert!(!is_allowed(
+            &request("PUT", "/fs/work/src/main.rs", &[]),
+            "/fs",
+            &exported_roots,
+            &writable_dirs
+        ));
+        assert!(!is_allowed(
+            &request("DELETE", "/fs/work/target/../src", &[]),
+            "/fs",
+            &exported_roots,
+            &writable_dirs
+        ));
+        assert!(!is_allowed(
+            &request(
+                "MOVE",
+                "/fs/work/target/a",
+                &[("Destination", "http://localhost:1234/fs/work/src/a")]
+            ),
+            "/fs",
+            &exported_roots,
+            &writable_dirs
+        ));
+        assert!(is_allowed(
+            &request(
+                "MOVE",
+                "/fs/work/target/a",
+                &[("Destination", "http://localhost:1234/fs/work/target/b")]
+            ),
+            "/fs",
+            &exported_roots,
+            &writable_dirs
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_is_allowed_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().canonicalize().unwrap();
+        let root = dir.join("work");
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::create_dir(dir.join("secrets")).unwrap();
+        std::fs::write(dir.join("secrets/key"), "key").unwrap();
+        std::os::unix::fs::symlink(dir.join("secrets"), root.join("leak")).unwrap();
+        std::os::unix::fs::symlink(root.join("src"), root.join("src-link")).unwrap();
+        let exported_roots = [root.clone()];
+        let writable_dirs = [root.clone()];
+        let url = |path: &Path| format!("/fs{}", path.display());
+
+        assert!(!is_allowed(
+            &request("GET", &url(&root.join("leak/key")), &[]),
+            "/fs",
+            &exported_roots,
+            &[]
+        ));
+        assert!(is_allowed(
+            &request("GET", &url(&root.join("src-link")), &[]),
+            "/fs",
+            &exported_roots,
+            &[]
+        ));
+        assert!(!is_allowed(
+            &request("PUT", &url(&root.join("leak/new.txt")), &[]),
+            "/fs",
+            &exported_roots,
+            &writable_dirs
+        ));
+        assert!(is_allowed(
+            &request("PUT", &url(&root.join("src/new/file.txt")), &[]),
+            "/fs",
+            &exported_roots,
+            &writable_dirs
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_list_tree() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().canonicalize().unwrap();
+        let root = dir.join("work");
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::create_dir_all(root.join("target/debug")).unwrap();
+        std::fs::write(root.join("target/CACHEDIR.TAG"), "").unwrap();
+        std::fs::write(root.join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(root.join("run.sh"), "").unwrap();
+        std::fs::set_permissions(root.join("run.sh"), std::fs::Permissions::from_mode(0o755))
+            .unwrap();
+        std::fs::write(dir.join("secret"), "key").unwrap();
+        std::os::unix::fs::symlink(dir.join("secret"), root.join("leak")).unwrap();
+
+        let entries = list_tree(&root, std::slice::from_ref(&root))
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let fields = line.split(' ').collect::<Vec<_>>();
+                format!("{} {} {}", fields[0], fields[1], fields[3])
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            ["x 0 run.sh", "d 0 src", "s 0 target", "f 12 src/main.rs"]
+        );
+
+        let listing = list_tree(&root.join("src/main.rs"), std::slice::from_ref(&root)).unwrap();
+        let modified = std::fs::metadata(root.join("src/main.rs"))
+            .unwrap()
+            .modified()
+            .unwrap()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        assert_eq!(listing, format!("f 12 {} \n", modified));
+    }
+
+    async fn get_status(port: u16, path: &str) -> String {
+        use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+        let mut stream = tokio::net::TcpStream::connect(("localhost", port))
+            .await
+            .unwrap();
+        stream
+            .write_all(format!("GET {} HTTP/1.0\r\n\r\n", path).as_bytes())
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response.lines().next().unwrap_or_default().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_serve_webdav_outcome() {
+        let (port, server_fut) = serve_webdav("secret", vec![], vec![], None).await.unwrap();
+        tokio::spawn(server_fut);
+
+        let url = format!("http://localhost:{}/secret/outcome/run1", port);
+        tokio::task::spawn_blocking(move || {
+            assert!(cargo_xrun_remote::http::get(&url, &mut Vec::new()).is_err());
+            cargo_xrun_remote::http::put(&url, b"signaled 11").unwrap();
+            let mut outcome = Vec::new();
+            cargo_xrun_remote::http::get(&url, &mut outcome).unwrap();
+            assert_eq!(outcome, b"signaled 11");
+            // Collecting the outcome removes it.
+            assert!(cargo_xrun_remote::http::get(&url, &mut Vec::new()).is_err());
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_serve_webdav_requires_token() {
+        let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let (port, server_fut) = serve_webdav("secret", vec![manifest_dir.clone()], vec![], None)
+            .await
+            .unwrap();
+        tokio::spawn(server_fut);
+
+        let manifest_url = format!("{}/Cargo.toml", manifest_dir.display());
+        assert!(
+            get_status(port, &format!("/secret/fs{}", manifest_url))
+                .await
+                .contains("200")
+        );
+        assert!(
+            get_status(port, &format!("/fs{}", manifest_url))
+                .await
+                .contains("403")
+        );
+        assert!(
+            get_status(port, &format!("/wrong/fs{}", manifest_url))
+                .await
+                .contains("403")
+        );
+    }
+}
@@ -0,0 +1,233 @@
+use std::path::PathBuf;
+
+use clap::{Args, Subcommand};
+
+use crate::config::{self, Host};
+
+/// `cargo xrun hosts`: edits the host mappings in the config file without prompting.
+#[derive(Debug, Subcommand)]
+pub enum HostsCommand {
+    /// List the configured hosts and the targets assigned to them
+    List,
+    /// Add a remote host
+    Add {
+        /// SSH destination, such as 'user@server.com'
+        destination: String,
+
+        /// Assign TRIPLE to the new host, moving it from the host it is assigned to
+        #[clap(long = "target", value_name = "TRIPLE")]
+        targets: Vec<String>,
+
+        #[clap(flatten)]
+        settings: HostSettings,
+    },
+    /// Remove a host along with its target assignments
+    Remove {
+        /// Alias or destination of the host
+        host: String,
+    },
+    /// Run TARGET on HOST, moving it from the hosts it is assigned to
+    Assign {
+        /// Target triple
+        target: String,
+        /// Alias or destination of the host
+        host: String,
+        /// Add HOST to the pool of hosts TARGET runs on instead of replacing them
+        #[clap(long)]
+        pool: bool,
+    },
+    /// Remove all host assignments of TARGET, so the next run asks for a host again
+    Unassign {
+        /// Target triple
+        target: String,
+    },
+    /// Change the settings of a host. Options that take a list replace the existing list
+    Edit {
+        /// Alias or destination of the host
+        host: String,
+
+        /// New SSH destination
+        #[clap(long)]
+        destination: Option<String>,
+
+        #[clap(flatten)]
+        clear: ClearSettings,
+
+        #[clap(flatten)]
+        settings: HostSettings,
+    },
+}
+
+/// Host settings that `add` and `edit` can set.
+#[derive(Debug, Args)]
+pub struct HostSettings {
+    /// Human-readable name to refer to the host by
+    #[clap(long)]
+    alias: Option<String>,
+
+    /// SSH port
+    #[clap(long)]
+    port: Option<u16>,
+
+    /// Private key to authenticate with
+    #[clap(long, value_name = "FILE")]
+    identity_file: Option<PathBuf>,
+
+    /// Jump host to connect through. Repeat for a chain of jump hosts
+    #[clap(long, value_name = "HOST")]
+    proxy_jump: Vec<String>,
+
+    /// Extra option for ssh, such as 'ServerAliveInterval=30'
+    #[clap(long = "ssh-option", value_name = "OPTION")]
+    ssh_options: Vec<String>,
+
+    /// Directory on the remote for the agent and its caches
+    #[clap(long, value_name = "DIR")]
+    remote_dir: Option<String>,
+
+    /// Set the environment variable KEY to VAL for remote processes by default
+    #[clap(long, value_name = "KEY=VAL", value_parser = crate::parse_env_assignment)]
+    env: Vec<(String, String)>,
+}
+
+/// Host settings that `edit` can remove. They are removed before the new settings apply, so
+/// `--clear-env --env KEY=VAL` replaces the environment.
+#[derive(Debug, Args, Default)]
+pub struct ClearSettings {
+    /// Remove the alias
+    #[clap(long, conflicts_with = "alias")]
+    clear_alias: bool,
+
+    /// Use the SSH port from ~/.ssh/config again
+    #[clap(long, conflicts_with = "port")]
+    clear_port: bool,
+
+    /// Use the identity files from ~/.ssh/config again
+    #[clap(long, conflicts_with = "identity_file")]
+    clear_identity_file: bool,
+
+    /// Connect without jump hosts
+    #[clap(long)]
+    clear_proxy_jump: bool,
+
+    /// Remove the extra ssh options
+    #[clap(long)]
+    clear_ssh_options: bool,
+
+    /// Use the per-user cache directory on the remote again
+    #[clap(long, conflicts_with = "remote_dir")]
+    clear_remote_dir: bool,
+
+    /// Remove the environment variables set for remote processes
+    #[clap(long)]
+    clear_env: bool,
+}
+
+impl ClearSettings {
+    fn apply(self, host: &mut Host) {
+        if self.clear_alias {
+            host.alias = None;
+        }
+        if self.clear_port {
+            host.port = None;
+        }
+        if self.clear_identity_file {
+            host.identity_file = None;
+        }
+        if self.clear_proxy_jump {
+            host.proxy_jump.clear();
+        }
+        if self.clear_ssh_options {
+            host.ssh_options.clear();
+        }
+        if self.clear_remote_dir {
+            host.remote_dir = None;
+        }
+        if self.clear_env {
+            host.env.clear();
+        }
+    }
+}
+
+impl HostSettings {
+    fn apply(self, host: &mut Host) {
+        if let Some(alias) = self.alias {
+            host.alias = Some(alias);
+        }
+        if let Some(port) = self.port {
+            host.port = Some(port);
+        }
+        if let Some(identity_file) = self.identity_file {
+            host.identity_file = Some(identity_file);
+        }
+        if !self.proxy_jump.is_empty() {
+            host.proxy_jump = self.proxy_jump;
+        }
+        if !self.ssh_options.is_empty() {
+            host.ssh_options = self.ssh_options;
+        }
+        if let Some(remote_dir) = self.remote_dir {
+            host.remote_dir = Some(remote_dir);
+        }
+        host.env.extend(self.env);
+    }
+}
+
+pub fn run(command: HostsCommand) -> anyhow::Result<()> {
+    match command {
+        HostsCommand::List => {
+            for host in config::load()?.hosts() {
+                match &host.alias {
+                    Some(alias) => print!("{} ({})", alias, host.destination),
+                    None => print!("{}", host.destination),
+                }
+                println!(": {}", host.targets.join(", "));
+            }
+        }
+        HostsCommand::Add {
+            destination,
+            targets,
+            settings,
+        } => {
+            let mut host = Host {
+                destination,
+                targets,
+                ..Default::default()
+            };
+            settings.apply(&mut host);
+            config::update(|config| config.add_host(host))?;
+        }
+        HostsCommand::Remove { host } => {
+            config::update(|config| config.remove_host(&host))?;
+        }
+        HostsCommand::Assign { target, host, pool } => {
+            config::update(|config| config.assign(&target, &host, pool))?;
+        }
+        HostsCommand::Unassign { target } => {
+            // Failing inside the update leaves the config file as it is.
+            config::update(|config| {
+                if config.unassign(&target).is_empty() {
+                    anyhow::bail!("Target '{}' is not assigned to any host", target);
+                }
+                Ok(())
+            })?;
+        }
+        HostsCommand::Edit {
+            host,
+            destination,
+            clear,
+            settings,
+        } => {
+            config::update(|config| {
+                config.edit_host(&host, |host| {
+                    if let Some(destination) = destination {
+                        host.destination = destination;
+                    }
+                    clear.apply(host);
+                    settings.apply(host);
+                })
+            })?;
+        }
+    }
+    Ok(())
+}
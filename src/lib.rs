@@ -1,14 +1,24 @@
+mod agent;
 mod config;
+#[cfg(unix)]
+mod daemon;
+mod doctor;
 mod embedded_binaries;
 mod fs_server;
+mod hosts;
+mod pattern;
+mod pool;
 mod runner;
+mod session;
 mod ssh_master;
+mod target;
+mod workspace;
 
 use anyhow::Context;
-use ssh_master::SshMaster;
 use std::{
     env::{self, args_os, current_exe},
     ffi::{OsStr, OsString},
+    path::PathBuf,
     process::{ExitCode, ExitStatus},
 };
 use tokio::process::Command;
@@ -16,6 +26,8 @@ use tokio::process::Command;
 use clap::Parser;
 use which::which;
 
+pub use session::{Outcome, RemoteCommand, RemoteOutput, RemoteSession, SessionOptions};
+
 /// Captures trailing arguments while preserving `--` separator.
 ///
 /// Clap normally consumes `--` as a delimiter without including it in captured args.
@@ -43,6 +55,92 @@ impl TrailingArgs {
     }
 }
 
+/// Options shared by `xrun` and `xtest`.
+#[derive(Debug, Parser)]
+struct RunArgs {
+    /// Build and run for the target triple
+    #[clap(name = "target", long, required = true)]
+    triple: Option<String>,
+
+    /// Run on HOST, the alias or destination of a configured host or any SSH destination, instead of the host the target is mapped to. Defaults to $CARGO_XRUN_HOST_<TRIPLE>, then $CARGO_XRUN_HOST.
+    #[clap(long, value_name = "HOST")]
+    host: Option<String>,
+
+    ///Command for building, defaulting to 'cargo'. Possible values include: 'cargo', 'cargo-zigbuild', and 'cargo-xwin'.
+    #[clap(name = "builder", long)]
+    builder: Option<String>,
+
+    /// How executables reach the remote. 'upload' copies them into a cache on the remote keyed by content hash, so unchanged executables are not transferred again.
+    #[clap(long, value_enum, default_value_t)]
+    exec_mode: runner::ExecMode,
+
+    /// Let remote processes write to the target directory, which includes CARGO_TARGET_TMPDIR. Windows targets only.
+    #[clap(long)]
+    allow_writes: bool,
+
+    /// Let remote processes write to DIR as well. Implies --allow-writes.
+    #[clap(long, value_name = "DIR")]
+    writable_dir: Vec<std::path::PathBuf>,
+
+    /// Translate arguments for the target binary that name an existing host path, such as './fixtures/a.toml' or '--config=./a.toml', into the matching remote path.
+    #[clap(long)]
+    detect_path_args: bool,
+
+    /// Translate arguments for the target binary matching the glob PATTERN as host paths, whether or not they exist. Applies to the value of '--name=value' arguments as well.
+    #[clap(long, value_name = "PATTERN")]
+    path_arg: Vec<String>,
+
+    /// Set the environment variable KEY to VAL for the target binary. Takes precedence over variables forwarded from the host.
+    #[clap(long, value_name = "KEY=VAL", value_parser = parse_env_assignment)]
+    env: Vec<(String, String)>,
+
+    /// Forward the host environment variable KEY to the target binary, even if the config denies it.
+    #[clap(long, value_name = "KEY")]
+    env_pass: Vec<String>,
+
+    /// Start the target binary with only the forwarded variables instead of on top of the remote user's environment.
+    #[clap(long)]
+    hermetic_env: bool,
+
+    /// Run the target binary in a pseudo-terminal on the remote, so it sees a terminal with the host's TERM and window size. 'auto' does so when stdout is a terminal.
+    #[clap(long, value_enum, default_value_t)]
+    tty: runner::TtyMode,
+
+    /// Start the target binary with NAME as argv[0] instead of its path. Ignored on Windows targets.
+    #[clap(long, value_name = "NAME")]
+    argv0: Option<String>,
+
+    /// Remove the environment variable KEY from the environment the target binary gets on the remote, including variables of the remote user's.
+    #[clap(long, value_name = "KEY")]
+    env_remove: Vec<String>,
+
+    /// Start the target binary with the file mode creation mask MODE, in octal such as 022. Ignored on Windows targets.
+    #[clap(long, value_name = "MODE", value_parser = parse_umask)]
+    umask: Option<u32>,
+
+    /// Where the target binary reads stdin from: 'inherit' to read cargo-xrun's, 'null', or 'file:PATH' to read the host file PATH.
+    #[clap(long, value_name = "SOURCE", default_value = "inherit")]
+    stdin: runner::StdinSource,
+
+    /// Kill the target binary once it has run for SECS seconds, and exit with 124.
+    #[clap(long, value_name = "SECS")]
+    timeout: Option<u64>,
+}
+
+fn parse_umask(arg: &str) -> Result<u32, String> {
+    match u32::from_str_radix(arg, 8) {
+        Ok(umask) if umask <= 0o777 => Ok(umask),
+        _ => Err(format!("expected an octal mode up to 777, got '{}'", arg)),
+    }
+}
+
+fn parse_env_assignment(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VAL, got '{}'", arg)),
+    }
+}
+
 #[derive(Debug, Parser)]
 #[command(
     version,
@@ -52,44 +150,64 @@ impl TrailingArgs {
 )]
 enum Opt {
     /// Run a binary or example of the local package remotely
-    #[command(name = "xrun", aliases = ["run", "r"])]
+    #[command(
+        name = "xrun",
+        aliases = ["run", "r"],
+        subcommand_negates_reqs = true,
+        args_conflicts_with_subcommands = true
+    )]
     XRun {
-        /// Build and run for the target triple
-        #[clap(name = "target", long, required = true)]
-        triple: String,
-
-        ///Command for building, defaulting to 'cargo'. Possible values include: 'cargo', 'cargo-zigbuild', and 'cargo-xwin'.
-        #[clap(name = "builder", long)]
-        builder: Option<String>,
+        #[clap(flatten)]
+        run_args: RunArgs,
 
         #[clap(flatten)]
         trailing_args: TrailingArgs,
+
+        #[command(subcommand)]
+        command: Option<XRunCommand>,
     },
     /// Run tests of the local package remotely
     #[command(name = "xtest", aliases = ["test", "t"])]
     XTest {
-        /// Build and run for the target triple
-        #[clap(name = "target", long, required = true)]
-        triple: String,
-
-        ///Command for building, defaulting to 'cargo'. Possible values include: 'cargo', 'cargo-zigbuild', and 'cargo-xwin'.
-        #[clap(name = "builder", long)]
-        builder: Option<String>,
+        #[clap(flatten)]
+        run_args: RunArgs,
 
         #[clap(flatten)]
         trailing_args: TrailingArgs,
     },
 }
 
+/// Subcommands of `cargo xrun` that manage cargo-xrun itself instead of running anything.
+#[derive(Debug, clap::Subcommand)]
+enum XRunCommand {
+    /// Manage the remote hosts and which targets run on them
+    Hosts {
+        #[command(subcommand)]
+        command: Box<hosts::HostsCommand>,
+    },
+    /// Check that a target's hosts are set up to run it, with hints for fixing what is not
+    Doctor(doctor::DoctorArgs),
+    /// Keep a session with a target's host open in the background, for later runs to reuse
+    #[cfg(unix)]
+    Session {
+        #[command(subcommand)]
+        command: daemon::SessionCommand,
+    },
+}
+
+/// Finds the executable for `builder`, which defaults to `$CARGO`, then `cargo`.
+fn resolve_builder(builder: Option<String>) -> anyhow::Result<PathBuf> {
+    let builder = builder.unwrap_or_else(|| env::var("CARGO").unwrap_or("cargo".into()));
+    which(&builder).with_context(|| format!("Failed to find executable {}", builder))
+}
+
 async fn exec_cargo(
     builder: Option<String>,
     subcommand: &str,
     args: impl IntoIterator<Item = impl AsRef<OsStr>>,
     envs: impl IntoIterator<Item = (impl AsRef<OsStr>, impl AsRef<OsStr>)>,
 ) -> anyhow::Result<ExitStatus> {
-    let builder = builder.unwrap_or_else(|| env::var("CARGO").unwrap_or("cargo".into()));
-    let cargo_path =
-        which(&builder).with_context(|| format!("Failed to find executable {}", builder))?;
+    let cargo_path = resolve_builder(builder)?;
 
     // https://github.com/rust-cross/cargo-zigbuild/blob/75aca8d5f0230a4cf3f116a0b6ab24c7b6124926/src/bin/cargo-zigbuild.rs#L92
     let mut cargo_command = Command::new(cargo_path);
@@ -104,147 +222,221 @@ async fn exec_cargo(
 
 pub async fn cli_main() -> anyhow::Result<ExitCode> {
     const RUNNER_MODE_SUBCOMMAND: &str = "cargo-xrun-runner-mode";
-    const SSH_CTRL_PATH_ENV_NAME: &str = "CARGOXRUN_SSH_CTRL_PATH";
-    const SSH_REMOTE_FS_SERVER_PORT: &str = "CARGOXRUN_SSH_REMOTE_FS_SERVER_PORT";
-    const SSH_DESTINATION_ENV_NAME: &str = "CARGOXRUN_SSH_DESTINATION";
+    const RUNNER_CONFIG_ENV_NAME: &str = "CARGOXRUN_RUNNER_CONFIG";
 
     let mut args = args_os();
     if let Some(_program_name) = args.next()
         && let Some(subcommand) = args.next()
         && subcommand == RUNNER_MODE_SUBCOMMAND
     {
-        let target = args.next().expect("target argument missing");
-        let target = target.to_str().expect("invalid target string");
-
-        let ssh_ctrl_path = env::var_os(SSH_CTRL_PATH_ENV_NAME)
-            .expect("CARGOXRUN_SSH_CTRL_PATH environment variable missing");
-        let ssh_remote_fs_server_port: u16 = env::var_os(SSH_REMOTE_FS_SERVER_PORT)
-            .expect("CARGOXRUN_SSH_REMOTE_FS_SERVER_PORT environment variable missing")
-            .to_str()
-            .and_then(|s| s.parse().ok())
-            .expect("invalid CARGOXRUN_SSH_REMOTE_FS_SERVER_PORT value");
-        let ssh_destination = env::var_os(SSH_DESTINATION_ENV_NAME)
-            .expect("CARGOXRUN_SSH_DESTINATION environment variable missing")
-            .into_string()
-            .expect("invalid CARGOXRUN_SSH_DESTINATION value");
-        return runner::runner(
-            target,
-            args,
-            &ssh_ctrl_path,
-            ssh_remote_fs_server_port,
-            &ssh_destination,
-        )
-        .await;
-    }
+        let _target = args.next().expect("target argument missing");
 
-    let current_exe_path = current_exe()?.into_os_string();
-    if contains_space(&current_exe_path) {
-        eprintln!(
-            "The path to cargo-xrun contains whitespace: {:?}\n\
-            This causes issues when used as a cargo runner.\n\
-            Please move cargo-xrun to a path without whitespace and retry.\n\
-            See <https://doc.rust-lang.org/cargo/reference/config.html#executable-paths-with-arguments> for details.",
-            &current_exe_path
-        );
-        std::process::exit(1);
+        let runner_config: runner::RunnerConfig = env::var(RUNNER_CONFIG_ENV_NAME)
+            .ok()
+            .and_then(|config| serde_json::from_str(&config).ok())
+            .expect("CARGOXRUN_RUNNER_CONFIG environment variable missing or invalid");
+        return runner::runner(args, &runner_config).await;
     }
 
     let opt = Opt::parse();
 
-    let (cargo_subcommand, triple, builder, args) = match opt {
+    let (cargo_subcommand, run_args, args) = match opt {
+        Opt::XRun {
+            command: Some(XRunCommand::Hosts { command }),
+            ..
+        } => {
+            hosts::run(*command)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Opt::XRun {
+            command: Some(XRunCommand::Doctor(args)),
+            ..
+        } => return doctor::run(args).await,
+        #[cfg(unix)]
         Opt::XRun {
-            triple,
-            builder,
+            command: Some(XRunCommand::Session { command }),
+            ..
+        } => return daemon::run(command).await,
+        Opt::XRun {
+            run_args,
             trailing_args,
-        } => ("run", triple, builder, trailing_args.into_args()),
+            command: None,
+        } => ("run", run_args, trailing_args.into_args()),
         Opt::XTest {
-            triple,
-            builder,
+            run_args,
             trailing_args,
-        } => ("test", triple, builder, trailing_args.into_args()),
+        } => ("test", run_args, trailing_args.into_args()),
     };
-
-    let args = [OsStr::new("--target"), OsStr::new(&triple)]
-        .into_iter()
-        .chain(args.iter().map(|arg| arg.as_os_str()));
-
-    let runner_env_name = OsString::from(format!(
-        "CARGO_TARGET_{}_RUNNER",
-        triple.to_uppercase().replace('-', "_"),
-    ));
-
-    let runner_env_value = {
-        let mut runner_command = current_exe_path.clone();
-        runner_command.push(" ");
-        runner_command.push(RUNNER_MODE_SUBCOMMAND);
-        runner_command.push(" ");
-        runner_command.push(&triple);
-        runner_command.push(" ");
+    let RunArgs {
+        triple,
+        host,
+        builder,
+        exec_mode,
+        allow_writes,
+        writable_dir,
+        detect_path_args,
+        path_arg,
+        env,
+        env_pass,
+        hermetic_env,
+        tty,
+        argv0,
+        env_remove,
+        umask,
+        stdin,
+        timeout,
+    } = run_args;
+    let triple = triple.context("--target is required")?;
+    let target_name = target::short_name(&triple);
+
+    // The array form of `target.<triple>.runner` keeps whitespace in the path to cargo-xrun
+    // intact, which `CARGO_TARGET_<triple>_RUNNER` would split on.
+    let runner_config_arg = {
+        let current_exe_path = current_exe()?;
+        let current_exe_path = current_exe_path.to_str().with_context(|| {
+            format!(
+                "The path to cargo-xrun is not valid UTF-8: {:?}",
+                current_exe_path
+            )
+        })?;
         // The executable path will be appended by cargo automatically
-        runner_command
+        let runner_command = [current_exe_path, RUNNER_MODE_SUBCOMMAND, target_name];
+        // JSON strings and arrays are valid TOML
+        format!(
+            "target.{}.runner={}",
+            serde_json::to_string(target_name)?,
+            serde_json::to_string(&runner_command)?
+        )
     };
 
-    let ssh_destination = config::get_ssh_destination(&triple)?;
+    let args = [
+        OsStr::new("--config"),
+        OsStr::new(&runner_config_arg),
+        OsStr::new("--target"),
+        OsStr::new(&triple),
+    ]
+    .into_iter()
+    .chain(args.iter().map(|arg| arg.as_os_str()));
+
+    let target_spec = target::TargetSpec::resolve(&triple).await?;
+    let target_config = config::load_for_target(target_name, host.as_deref())?;
+    let metadata = workspace::metadata().await?;
+    let manifest_settings = config::manifest::load_for_target(&metadata, target_name)?;
+    let builder = builder.or(manifest_settings.builder);
+
+    let mut writable_dirs = Vec::new();
+    if allow_writes || !writable_dir.is_empty() {
+        writable_dirs.push(metadata.target_directory.clone());
+        for dir in writable_dir {
+            writable_dirs.push(std::path::absolute(dir)?);
+        }
+    }
+    let exported_roots = workspace::exported_roots(
+        &metadata,
+        target_config
+            .exported_paths
+            .iter()
+            .cloned()
+            .chain(manifest_settings.exported_paths)
+            .chain(writable_dirs.iter().cloned()),
+    )
+    .await?;
 
-    let (dav_port, server_fut) = fs_server::serve_webdav().await?;
-    tokio::spawn(server_fut);
+    // The runner reads the file from where cargo runs it, which is not the current directory for
+    // `cargo test`.
+    let stdin = match stdin {
+        runner::StdinSource::File(path) => runner::StdinSource::File(std::path::absolute(path)?),
+        stdin => stdin,
+    };
+    let launch = runner::LaunchOptions {
+        argv0,
+        env_remove,
+        umask,
+        stdin,
+        timeout_secs: timeout,
+    };
 
-    let ssh_master = SshMaster::start(&ssh_destination, dav_port).await?;
+    // A session daemon's file server is read-only. Daemons listen on Unix sockets, so other hosts
+    // always open a session of their own.
+    #[cfg(unix)]
+    let daemon = if writable_dirs.is_empty() {
+        daemon::find(target_name, &target_config.hosts, &exported_roots)
+            .await
+            .and_then(|daemon| Some((daemon.host, daemon.runner_config?)))
+    } else {
+        None
+    };
+    #[cfg(not(unix))]
+    let daemon = None;
+    let (host, session_config, session) = match daemon {
+        Some((host, session_config)) => (host, session_config, None),
+        None => {
+            let session = RemoteSession::open(
+                &target_spec,
+                &target_config,
+                exported_roots,
+                writable_dirs,
+                exec_mode.required_capabilities() | launch.required_capabilities(),
+            )
+            .await?;
+            let host = session.host().clone();
+            let session_config = session.runner_config().clone();
+            (host, session_config, Some(session))
+        }
+    };
+
+    // Manifest settings layer over the user config, apart from the host's own settings, and
+    // command line flags over both.
+    let mut path_args = target_config.path_args;
+    path_args.detect_existing |= manifest_settings.path_args.detect_existing | detect_path_args;
+    path_args
+        .patterns
+        .extend(manifest_settings.path_args.patterns);
+    path_args.patterns.extend(path_arg);
+    let mut env_forward = host.env_forward.clone();
+    env_forward.merge(target_config.env_forward);
+    env_forward.merge(manifest_settings.env_forward);
+    env_forward.hermetic |= hermetic_env;
+    let mut env_defaults = manifest_settings.env;
+    env_defaults.extend(host.env.clone());
+    let env_policy = runner::EnvPolicy {
+        forward: env_forward,
+        pass: env_pass,
+        set: env,
+        defaults: env_defaults,
+    };
+
+    let runner_config = runner::RunnerConfig {
+        exec_mode,
+        path_args,
+        env: env_policy,
+        tty,
+        launch,
+        ..session_config
+    };
+    let runner_config = serde_json::to_string(&runner_config)?;
 
     let cargo_status = exec_cargo(
         builder,
         cargo_subcommand,
         args,
-        [
-            (runner_env_name.as_os_str(), runner_env_value.as_os_str()),
-            (
-                OsStr::new(SSH_CTRL_PATH_ENV_NAME),
-                ssh_master.control_path().as_os_str(),
-            ),
-            (
-                OsStr::new(SSH_CTRL_PATH_ENV_NAME),
-                ssh_master.control_path().as_os_str(),
-            ),
-            (
-                OsStr::new(SSH_REMOTE_FS_SERVER_PORT),
-                OsStr::new(&ssh_master.remote_port().to_string()),
-            ),
-            (
-                OsStr::new(SSH_DESTINATION_ENV_NAME),
-                OsStr::new(&ssh_destination),
-            ),
-        ],
+        [(
+            OsStr::new(RUNNER_CONFIG_ENV_NAME),
+            OsStr::new(&runner_config),
+        )],
     )
     .await?;
 
-    let _ = ssh_master.stop().await?;
+    if let Some(session) = session {
+        session.shutdown().await?;
+    }
     Ok((cargo_status.code().unwrap_or(1) as u8).into())
 }
 
-fn contains_space(s: impl AsRef<OsStr>) -> bool {
-    #[cfg(unix)]
-    return std::os::unix::ffi::OsStrExt::as_bytes(s.as_ref())
-        .iter()
-        .any(u8::is_ascii_whitespace);
-
-    #[cfg(windows)]
-    std::os::windows::ffi::OsStrExt::encode_wide(s.as_ref()).any(|ch| {
-        char::from_u32(ch.into())
-            .map(|c| c.is_whitespace())
-            .unwrap_or(false)
-    })
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_contains_space() {
-        assert!(contains_space("hello world"));
-        assert!(!contains_space("helloworld"));
-    }
-
     #[test]
     fn test_xrun_without_separator() {
         let opt =
@@ -304,4 +496,205 @@ mod tests {
             _ => panic!("expected XRun"),
         }
     }
+
+    #[test]
+    fn test_xtest_exec_mode() {
+        let opt = Opt::parse_from([
+            "cargo-xrun",
+            "xtest",
+            "--target",
+            "aarch64-unknown-linux-gnu",
+        ]);
+        match opt {
+            Opt::XTest { run_args, .. } => assert_eq!(run_args.exec_mode, runner::ExecMode::Webdav),
+            _ => panic!("expected XTest"),
+        }
+
+        let opt = Opt::parse_from([
+            "cargo-xrun",
+            "xtest",
+            "--target",
+            "aarch64-unknown-linux-gnu",
+            "--exec-mode",
+            "upload",
+        ]);
+        match opt {
+            Opt::XTest { run_args, .. } => assert_eq!(run_args.exec_mode, runner::ExecMode::Upload),
+            _ => panic!("expected XTest"),
+        }
+    }
+
+    #[test]
+    fn test_xrun_launch_options() {
+        let opt = Opt::parse_from([
+            "cargo-xrun",
+            "xrun",
+            "--target",
+            "aarch64-unknown-linux-gnu",
+            "--umask",
+            "027",
+            "--stdin",
+            "file:fixtures/input.txt",
+            "--timeout",
+            "30",
+            "--env-remove",
+            "LANG",
+        ]);
+        match opt {
+            Opt::XRun { run_args, .. } => {
+                assert_eq!(run_args.umask, Some(0o027));
+                assert_eq!(
+                    run_args.stdin,
+                    runner::StdinSource::File("fixtures/input.txt".into())
+                );
+                assert_eq!(run_args.timeout, Some(30));
+                assert_eq!(run_args.env_remove, ["LANG"]);
+            }
+            _ => panic!("expected XRun"),
+        }
+
+        for (flag, value) in [("--umask", "1000"), ("--umask", "9"), ("--stdin", "file:")] {
+            assert!(
+                Opt::try_parse_from([
+                    "cargo-xrun",
+                    "xrun",
+                    "--target",
+                    "aarch64-unknown-linux-gnu",
+                    flag,
+                    value,
+                ])
+                .is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn test_xrun_tty() {
+        let opt = Opt::parse_from([
+            "cargo-xrun",
+            "xrun",
+            "--target",
+            "aarch64-unknown-linux-gnu",
+        ]);
+        match opt {
+            Opt::XRun { run_args, .. } => assert_eq!(run_args.tty, runner::TtyMode::Auto),
+            _ => panic!("expected XRun"),
+        }
+
+        let opt = Opt::parse_from([
+            "cargo-xrun",
+            "xrun",
+            "--target",
+            "aarch64-unknown-linux-gnu",
+            "--tty",
+            "never",
+        ]);
+        match opt {
+            Opt::XRun { run_args, .. } => assert_eq!(run_args.tty, runner::TtyMode::Never),
+            _ => panic!("expected XRun"),
+        }
+    }
+
+    #[test]
+    fn test_xrun_env_flags() {
+        let opt = Opt::parse_from([
+            "cargo-xrun",
+            "xrun",
+            "--target",
+            "aarch64-unknown-linux-gnu",
+            "--env",
+            "RUST_LOG=debug,hyper=info",
+            "--env-pass",
+            "HOME",
+            "--hermetic-env",
+        ]);
+        match opt {
+            Opt::XRun { run_args, .. } => {
+                assert_eq!(
+                    run_args.env,
+                    vec![("RUST_LOG".to_string(), "debug,hyper=info".to_string())]
+                );
+                assert_eq!(run_args.env_pass, vec!["HOME"]);
+                assert!(run_args.hermetic_env);
+            }
+            _ => panic!("expected XRun"),
+        }
+
+        assert!(
+            Opt::try_parse_from([
+                "cargo-xrun",
+                "xrun",
+                "--target",
+                "aarch64-unknown-linux-gnu",
+                "--env",
+                "RUST_LOG",
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_xrun_hosts() {
+        let opt = Opt::parse_from([
+            "cargo-xrun",
+            "xrun",
+            "hosts",
+            "assign",
+            "aarch64-unknown-linux-gnu",
+            "arm-box",
+        ]);
+        match opt {
+            Opt::XRun {
+                command: Some(XRunCommand::Hosts { command }),
+                ..
+            } => {
+                let hosts::HostsCommand::Assign { target, host, .. } = *command else {
+                    panic!("expected hosts assign");
+                };
+                assert_eq!(target, "aarch64-unknown-linux-gnu");
+                assert_eq!(host, "arm-box");
+            }
+            _ => panic!("expected XRun hosts assign"),
+        }
+
+        // Without a subcommand, --target is still required, and arguments for cargo that happen
+        // to be named like a subcommand are passed through.
+        assert!(Opt::try_parse_from(["cargo-xrun", "xrun", "foo"]).is_err());
+        let opt = Opt::parse_from([
+            "cargo-xrun",
+            "xrun",
+            "--target",
+            "aarch64-unknown-linux-gnu",
+            "hosts",
+        ]);
+        match opt {
+            Opt::XRun {
+                trailing_args,
+                command: None,
+                ..
+            } => assert_eq!(trailing_args.into_args(), vec!["hosts"]),
+            _ => panic!("expected XRun"),
+        }
+    }
+
+    #[test]
+    fn test_xrun_doctor() {
+        let opt = Opt::parse_from([
+            "cargo-xrun",
+            "xrun",
+            "doctor",
+            "--target",
+            "x86_64-pc-windows-msvc",
+            "--host",
+            "win-box",
+        ]);
+        assert!(matches!(
+            opt,
+            Opt::XRun {
+                command: Some(XRunCommand::Doctor(_)),
+                ..
+            }
+        ));
+        assert!(Opt::try_parse_from(["cargo-xrun", "xrun", "doctor"]).is_err());
+    }
 }
@@ -0,0 +1,45 @@
+/// Matches `text` against a glob `pattern` where `*` matches any run of characters and `?`
+/// matches a single character. Everything else matches literally.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text position it was tried at, for backtracking
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("RUST_*", "RUST_LOG"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*.toml", "fixtures/a.toml"));
+        assert!(glob_match("a?c", "abc"));
+        assert!(glob_match("*_TEST_*", "MY_TEST_VAR"));
+        assert!(!glob_match("RUST_*", "CARGO_HOME"));
+        assert!(!glob_match("*.toml", "a.toml.bak"));
+        assert!(!glob_match("a?c", "ac"));
+        assert!(glob_match("exact", "exact"));
+        assert!(!glob_match("exact", "exactly"));
+    }
+}
@@ -0,0 +1,198 @@
+//! Picking a reachable host among the hosts assigned to a target.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context as _;
+use futures_util::{StreamExt as _, stream::FuturesUnordered};
+
+use crate::{
+    config::{Host, PoolStrategy},
+    ssh_master::SshMaster,
+    target::TargetOs,
+};
+
+/// How long to wait for each host of a pool before moving on, unless its `ssh_options` say
+/// otherwise.
+const CONNECT_TIMEOUT_SECS: u32 = 10;
+
+/// File recording when each host was last used, keyed by destination, for [`PoolStrategy::Lru`].
+fn last_used_path() -> anyhow::Result<PathBuf> {
+    Ok(dirs::cache_dir()
+        .context("Could not determine cache directory")?
+        .join("cargo-xrun")
+        .join("last-used.json"))
+}
+
+fn read_last_used() -> HashMap<String, u64> {
+    last_used_path()
+        .ok()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+/// Records that `host` was just used. A lost update only makes the host look less recently used
+/// than it is, so this does without locking.
+fn record_last_used(host: &Host) -> anyhow::Result<()> {
+    let path = last_used_path()?;
+    let dir = path.parent().unwrap();
+    std::fs::create_dir_all(dir)?;
+
+    let mut last_used = read_last_used();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    last_used.insert(host.destination.clone(), now);
+
+    let file = tempfile::NamedTempFile::new_in(dir)?;
+    serde_json::to_writer(&file, &last_used)?;
+    file.persist(&path)?;
+    Ok(())
+}
+
+/// Orders `hosts` by when they were last used. Hosts never used come first, in config order.
+fn least_recently_used_first<'a>(
+    hosts: &'a [Host],
+    last_used: &HashMap<String, u64>,
+) -> Vec<&'a Host> {
+    let mut hosts: Vec<&Host> = hosts.iter().collect();
+    hosts.sort_by_key(|host| last_used.get(&host.destination).copied().unwrap_or(0));
+    hosts
+}
+
+/// `host` with a connect timeout, so an unreachable host does not hold up the pool for the
+/// system's TCP timeout.
+pub fn with_connect_timeout(host: &Host) -> Host {
+    let mut host = host.clone();
+    let has_timeout = host
+        .ssh_options
+        .iter()
+        .any(|option| option.to_ascii_lowercase().starts_with("connecttimeout"));
+    if !has_timeout {
+        host.ssh_options
+            .push(format!("ConnectTimeout={}", CONNECT_TIMEOUT_SECS));
+    }
+    host
+}
+
+/// Connects to one of `hosts` according to `strategy`, skipping hosts that cannot be reached.
+pub async fn connect(
+    hosts: &[Host],
+    strategy: PoolStrategy,
+    forward_port: u16,
+    os: TargetOs,
+) -> anyhow::Result<(Host, SshMaster)> {
+    // A single host needs no probing, and its errors are best reported as they are.
+    if let [host] = hosts {
+        let ssh_master = SshMaster::start(host, forward_port, os).await?;
+        return Ok((host.clone(), ssh_master));
+    }
+
+    let connected = match strategy {
+        PoolStrategy::First => connect_in_order(hosts.iter(), forward_port, os).await,
+        PoolStrategy::Lru => {
+            let hosts = least_recently_used_first(hosts, &read_last_used());
+            connect_in_order(hosts.into_iter(), forward_port, os).await
+        }
+        PoolStrategy::Fastest => connect_fastest(hosts, forward_port, os).await,
+    };
+    let (host, ssh_master) = connected.with_context(|| {
+        format!(
+            "None of the hosts for this target could be reached: {}",
+            hosts.iter().map(Host::name).collect::<Vec<_>>().join(", ")
+        )
+    })?;
+
+    if let Err(err) = record_last_used(&host) {
+        eprintln!(
+            "cargo-xrun: failed to record the last use of {}: {:#}",
+            host.name(),
+            err
+        );
+    }
+    Ok((host, ssh_master))
+}
+
+async fn connect_in_order(
+    hosts: impl Iterator<Item = &Host>,
+    forward_port: u16,
+    os: TargetOs,
+) -> Option<(Host, SshMaster)> {
+    for host in hosts {
+        match SshMaster::start(&with_connect_timeout(host), forward_port, os).await {
+            Ok(ssh_master) => return Some((host.clone(), ssh_master)),
+            Err(err) => eprintln!("cargo-xrun: skipping {}: {:#}", host.name(), err),
+        }
+    }
+    None
+}
+
+/// Connects to all hosts at once and keeps the first connection that comes up.
+async fn connect_fastest(
+    hosts: &[Host],
+    forward_port: u16,
+    os: TargetOs,
+) -> Option<(Host, SshMaster)> {
+    let mut attempts = hosts
+        .iter()
+        .map(|host| {
+            let host = host.clone();
+            async move {
+                let result = SshMaster::start(&with_connect_timeout(&host), forward_port, os).await;
+                (host, result)
+            }
+        })
+        .collect::<FuturesUnordered<_>>();
+
+    while let Some((host, result)) = attempts.next().await {
+        match result {
+            Ok(ssh_master) => {
+                // Let the slower attempts finish in the background; their masters shut down as
+                // soon as they are dropped.
+                tokio::spawn(async move { while attempts.next().await.is_some() {} });
+                return Some((host, ssh_master));
+            }
+            Err(err) => eprintln!("cargo-xrun: skipping {}: {:#}", host.name(), err),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_connect_timeout() {
+        let host = Host::default();
+        assert_eq!(
+            with_connect_timeout(&host).ssh_options,
+            ["ConnectTimeout=10"]
+        );
+
+        let host = Host {
+            ssh_options: vec!["ConnectTimeout=3".into()],
+            ..Default::default()
+        };
+        assert_eq!(
+            with_connect_timeout(&host).ssh_options,
+            ["ConnectTimeout=3"]
+        );
+    }
+
+    #[test]
+    fn test_least_recently_used_first() {
+        let hosts = ["a", "b", "c"].map(|destination| Host {
+            destination: destination.into(),
+            ..Default::default()
+        });
+        let last_used = HashMap::from([("a".to_string(), 20), ("b".to_string(), 10)]);
+        let order: Vec<&str> = least_recently_used_first(&hosts, &last_used)
+            .into_iter()
+            .map(|host| host.destination.as_str())
+            .collect();
+        assert_eq!(order, ["c", "b", "a"]);
+    }
+}
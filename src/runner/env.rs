@@ -0,0 +1,70 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::EnvForward, pattern::glob_match};
+
+/// Environment of the remote process as requested for this session.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct EnvPolicy {
+    /// Patterns from the config, merged with `--hermetic-env`.
+    pub forward: EnvForward,
+    /// Variables forwarded by `--env-pass`, even if denied.
+    pub pass: Vec<String>,
+    /// Variables set by `--env`, overriding anything forwarded from the host.
+    pub set: Vec<(String, String)>,
+    /// Variables from the host config, used unless forwarded from the host or set by `--env`.
+    pub defaults: BTreeMap<String, String>,
+}
+
+impl EnvPolicy {
+    /// Whether the host variable `name` is forwarded to the remote process.
+    pub fn forwards(&self, name: &str) -> bool {
+        if self.pass.iter().any(|pass| pass == name) {
+            return true;
+        }
+        if self
+            .forward
+            .deny
+            .iter()
+            .any(|pattern| glob_match(pattern, name))
+        {
+            return false;
+        }
+        name == "CARGO"
+            || name.starts_with("CARGO_")
+            || self
+                .forward
+                .allow
+                .iter()
+                .any(|pattern| glob_match(pattern, name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forwards() {
+        let policy = EnvPolicy {
+            forward: EnvForward {
+                allow: vec!["RUST_*".into()],
+                deny: vec!["RUST_MIN_STACK".into(), "CARGO_HOME".into()],
+                hermetic: false,
+            },
+            pass: vec!["CARGO_HOME".into(), "HOME".into()],
+            set: vec![],
+            defaults: BTreeMap::new(),
+        };
+
+        assert!(policy.forwards("CARGO"));
+        assert!(policy.forwards("CARGO_PKG_NAME"));
+        assert!(policy.forwards("RUST_LOG"));
+        assert!(!policy.forwards("RUST_MIN_STACK"));
+        assert!(!policy.forwards("PATH"));
+        // `--env-pass` wins over deny patterns.
+        assert!(policy.forwards("CARGO_HOME"));
+        assert!(policy.forwards("HOME"));
+    }
+}
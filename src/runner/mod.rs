@@ -1,113 +1,402 @@
-use std::{ffi::OsStr, process::ExitCode};
+mod env;
+mod outcome;
+mod path_args;
+mod upload;
+
+use std::{
+    ffi::OsStr,
+    io::IsTerminal as _,
+    path::PathBuf,
+    process::{ExitCode, Stdio},
+};
 
 use anyhow::Context as _;
-use cargo_xrun_remote::{ExecContext, encode::encode_context};
+use cargo_xrun_remote::{ExecContext, StdinMode, encode::encode_context, outcome::Outcome};
+use serde::{Deserialize, Serialize};
 use tokio::process::Command;
 
-pub async fn runner(
-    target: &str,
-    mut args: impl Iterator<Item = impl AsRef<OsStr>>,
-    ssh_ctrl_path: &OsStr,
-    ssh_remote_fs_server_port: u16,
-    ssh_destination: &str,
-) -> anyhow::Result<ExitCode> {
-    let to_remote_path = |path: &OsStr| -> anyhow::Result<String> {
-        let path = std::path::absolute(path)?
-            .into_os_string()
-            .into_string()
-            .ok()
-            .context("Path is not valid UTF-8")?;
-        if path.contains(char::is_whitespace) {
+use crate::{config, fs_server, target::TargetOs, workspace};
+
+pub use env::EnvPolicy;
+
+/// How the executable built by cargo reaches the remote.
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExecMode {
+    /// Run the executable straight off the host's WebDAV share.
+    #[default]
+    Webdav,
+    /// Upload the executable into a content-addressed cache on the remote and run the cached copy.
+    Upload,
+}
+
+impl ExecMode {
+    /// Agent capabilities runs in this mode rely on.
+    pub fn required_capabilities(self) -> u32 {
+        use cargo_xrun_remote::protocol::{CAP_CACHE, CAP_CLEAR_ENV, CAP_OUTCOME};
+        match self {
+            ExecMode::Webdav => CAP_OUTCOME | CAP_CLEAR_ENV,
+            ExecMode::Upload => CAP_OUTCOME | CAP_CLEAR_ENV | CAP_CACHE,
+        }
+    }
+}
+
+/// Whether the remote process gets a pseudo-terminal.
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TtyMode {
+    /// Allocate one when stdout is a terminal.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl TtyMode {
+    fn enabled(self) -> bool {
+        match self {
+            TtyMode::Auto => std::io::stdout().is_terminal(),
+            TtyMode::Always => true,
+            TtyMode::Never => false,
+        }
+    }
+}
+
+/// Where the target binary reads stdin from.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub enum StdinSource {
+    /// The ssh session's, and so cargo-xrun's.
+    #[default]
+    Inherit,
+    Null,
+    /// A host file, read through the file server.
+    File(PathBuf),
+}
+
+impl std::str::FromStr for StdinSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "inherit" => Ok(StdinSource::Inherit),
+            "null" => Ok(StdinSource::Null),
+            _ => match s.strip_prefix("file:") {
+                Some(path) if !path.is_empty() => Ok(StdinSource::File(path.into())),
+                _ => Err(format!(
+                    "expected 'inherit', 'null' or 'file:PATH', got '{}'",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+/// How the agent starts the target binary, beyond its arguments and environment.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LaunchOptions {
+    pub argv0: Option<String>,
+    pub env_remove: Vec<String>,
+    pub umask: Option<u32>,
+    pub stdin: StdinSource,
+    pub timeout_secs: Option<u64>,
+}
+
+impl LaunchOptions {
+    /// Agent capabilities runs with these options rely on.
+    pub fn required_capabilities(&self) -> u32 {
+        if self.argv0.is_some()
+            || !self.env_remove.is_empty()
+            || self.umask.is_some()
+            || self.stdin != StdinSource::Inherit
+            || self.timeout_secs.is_some()
+        {
+            cargo_xrun_remote::protocol::CAP_LAUNCH_OPTIONS
+        } else {
+            0
+        }
+    }
+}
+
+/// Session state handed from `cli_main` to the runner processes cargo spawns.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunnerConfig {
+    pub target_os: TargetOs,
+    pub ssh_ctrl_path: PathBuf,
+    pub ssh_destination: String,
+    /// Port of the file server on the host, where the runner collects exit outcomes.
+    pub fs_server_port: u16,
+    pub remote_fs_server_port: u16,
+    /// Per-session token the WebDAV server requires as the first path segment.
+    pub fs_server_token: String,
+    /// Id of the session on the remote, which stands in for the token in the contexts sent to the
+    /// agent, so the token stays off the remote's command lines.
+    pub session_id: String,
+    pub remote_agent_path: String,
+    pub exec_mode: ExecMode,
+    /// Host directories the WebDAV server exposes; other paths cannot be mapped to the remote.
+    pub exported_roots: Vec<PathBuf>,
+    pub path_args: config::PathArgs,
+    pub env: EnvPolicy,
+    pub tty: TtyMode,
+    pub launch: LaunchOptions,
+}
+
+impl RunnerConfig {
+    /// Creates an `ssh` command that opens a new session over the master connection, optionally
+    /// with a PTY. With a PTY, the ssh client also forwards the window size and later resizes.
+    fn ssh_command(&self, tty: bool) -> Command {
+        let mut command = Command::new("ssh");
+        command
+            .arg("-S")
+            .arg(&self.ssh_ctrl_path)
+            .args(["-o", "PreferredAuthentications=none"]);
+        if tty {
+            command.arg("-tt");
+        }
+        command.arg(&self.ssh_destination);
+        command
+    }
+
+    /// Root of the file server share as the agent sees it, with the session id in place of the
+    /// token. Windows reaches it through WebClient's UNC paths. On Linux the agent talks HTTP to it
+    /// directly and maps the POSIX-style URL paths onto a local mirror, so programs there see a
+    /// snapshot of the host files taken as they start (see [`ExecContext`]).
+    pub fn webdav_path(&self) -> String {
+        match self.target_os {
+            TargetOs::Windows => format!(
+                "\\\\localhost@{}\\DavWWWRoot\\{}",
+                self.remote_fs_server_port, self.session_id
+            ),
+            TargetOs::Linux => format!(
+                "http://localhost:{}/{}",
+                self.remote_fs_server_port, self.session_id
+            ),
+        }
+    }
+
+    /// Path on the remote of the host path `path`, which must lie in an exported root.
+    pub fn to_remote_path(&self, path: &OsStr) -> anyhow::Result<String> {
+        let path = std::path::absolute(path)?;
+        if !workspace::is_exported(&path, &self.exported_roots) {
             anyhow::bail!(
-                "Path contains whitespace, which is not supported for remote execution: {}",
-                path
+                "{:?} is outside the host directories exported to the remote ({}).\n\
+                To export it, add it to \"exported_paths\" in {}",
+                path,
+                self.exported_roots
+                    .iter()
+                    .map(|root| root.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                config::config_path()
+                    .map(|path| path.display().to_string())
+                    .unwrap_or_else(|_| "the cargo-xrun config file".into())
             );
         }
-        let path = path.replace("/", "\\");
-        Ok(format!(
-            "\\\\localhost@{}\\DavWWWRoot\\fs\\{}",
-            ssh_remote_fs_server_port,
-            path.trim_start_matches('\\')
-        ))
-    };
+        // Paths are carried as UTF-8 and escaped where they become URLs: WebClient encodes UNC
+        // paths itself, and the Linux agent percent-encodes its requests.
+        let path = path
+            .into_os_string()
+            .into_string()
+            .map_err(|path| anyhow::anyhow!("Path is not valid UTF-8: {:?}", path))?;
+        Ok(match self.target_os {
+            TargetOs::Windows => {
+                let path = path.replace("/", "\\");
+                format!(
+                    "{}\\fs\\{}",
+                    self.webdav_path(),
+                    path.trim_start_matches('\\')
+                )
+            }
+            TargetOs::Linux => {
+                let path = path.replace("\\", "/");
+                format!("{}/fs/{}", self.webdav_path(), path.trim_start_matches('/'))
+            }
+        })
+    }
+
+    /// Where the agent should read stdin from for `stdin`.
+    pub fn remote_stdin(&self, stdin: &StdinSource) -> anyhow::Result<StdinMode> {
+        Ok(match stdin {
+            StdinSource::Inherit => StdinMode::Inherit,
+            StdinSource::Null => StdinMode::Null,
+            StdinSource::File(path) => StdinMode::File(self.to_remote_path(path.as_os_str())?),
+        })
+    }
+
+    /// Runs `ctx` through the agent in a new ssh session, capturing the session's stdout and
+    /// stderr if `capture` is set and inheriting them otherwise.
+    pub async fn exec(
+        &self,
+        mut ctx: ExecContext,
+        tty: bool,
+        capture: bool,
+    ) -> anyhow::Result<Execution> {
+        let run_id = fs_server::generate_token();
+        ctx.session = self.session_id.clone();
+        ctx.report_url = format!(
+            "http://localhost:{}/{}/outcome/{}",
+            self.remote_fs_server_port, self.session_id, run_id
+        );
+        let encoded = encode_context(&ctx);
+
+        let mut command = self.ssh_command(tty);
+        command
+            .arg(self.target_os.quote_arg(&self.remote_agent_path))
+            .arg(&encoded);
+        // ssh forwards its stdin to the remote session, so only hand it over when the executable
+        // reads from it.
+        if tty || matches!(ctx.stdin, StdinMode::Inherit) {
+            command.stdin(Stdio::inherit());
+        } else {
+            command.stdin(Stdio::null());
+        }
+        let output = if capture {
+            command.output().await?
+        } else {
+            std::process::Output {
+                status: command.status().await?,
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+            }
+        };
+
+        // The agent reports how the executable ended; the exit status of ssh alone cannot tell a
+        // program exiting with 255 from a failed connection, and truncates Windows exception
+        // codes.
+        let outcome_url = format!(
+            "http://localhost:{}/{}/outcome/{}",
+            self.fs_server_port, self.fs_server_token, run_id
+        );
+        let outcome = tokio::task::spawn_blocking(move || {
+            let mut body = Vec::new();
+            cargo_xrun_remote::http::get(&outcome_url, &mut body).ok()?;
+            Outcome::decode(std::str::from_utf8(&body).ok()?)
+        })
+        .await?;
+        Ok(Execution { outcome, output })
+    }
+}
+
+/// A run of the agent.
+pub struct Execution {
+    /// How the executable ended, unless the agent failed before reporting it.
+    pub outcome: Option<Outcome>,
+    pub output: std::process::Output,
+}
+
+impl Execution {
+    /// Whether the ssh session itself failed, as opposed to the agent.
+    pub fn ssh_failed(&self) -> bool {
+        self.outcome.is_none() && self.output.status.code() == Some(255)
+    }
+}
+
+pub async fn runner(
+    mut args: impl Iterator<Item = impl AsRef<OsStr>>,
+    config: &RunnerConfig,
+) -> anyhow::Result<ExitCode> {
+    let target_os = config.target_os;
 
     let mut envs = Vec::new();
     for (env_name, env_value) in std::env::vars_os() {
         let Ok(env_name) = env_name.into_string() else {
             continue;
         };
+        if !config.env.forwards(&env_name) {
+            continue;
+        }
         if env_name == "CARGO"
             || env_name == "CARGO_MANIFEST_DIR"
             || env_name == "CARGO_MANIFEST_PATH"
             || env_name.starts_with("CARGO_BIN_EXE_")
         {
-            let env_value = to_remote_path(&env_value)?;
+            let env_value = config.to_remote_path(&env_value)?;
             envs.push((env_name, env_value));
-        } else if env_name.starts_with("CARGO_") {
+        } else {
             let env_value = env_value
                 .into_string()
-                .ok()
-                .context("Env value is not valid UTF-8")?;
+                .map_err(|_| anyhow::anyhow!("Value of {} is not valid UTF-8", env_name))?;
             envs.push((env_name, env_value));
         }
     }
+    // ssh sets TERM for the remote PTY, but a hermetic environment would drop it again, and
+    // COLORTERM is not carried at all.
+    let tty = config.tty.enabled();
+    if tty {
+        for env_name in ["TERM", "COLORTERM"] {
+            if let Ok(env_value) = std::env::var(env_name)
+                && !envs.iter().any(|(name, _)| name == env_name)
+            {
+                envs.push((env_name.to_string(), env_value));
+            }
+        }
+    }
+    for (env_name, env_value) in &config.env.defaults {
+        if !envs.iter().any(|(name, _)| name == env_name) {
+            envs.push((env_name.clone(), env_value.clone()));
+        }
+    }
+    for (env_name, env_value) in &config.env.set {
+        envs.retain(|(name, _)| name != env_name);
+        envs.push((env_name.clone(), env_value.clone()));
+    }
+    let launch = &config.launch;
+    envs.retain(|(name, _)| !launch.env_remove.contains(name));
 
     let cwd = std::env::current_dir().context("Failed to get current directory")?;
-    let remote_cwd = to_remote_path(cwd.as_os_str())?;
+    let remote_cwd = config.to_remote_path(cwd.as_os_str())?;
 
     let exe = args.next().context("executable argument missing")?;
-    let bin_path = to_remote_path(exe.as_ref())?;
+    let bin_path = match config.exec_mode {
+        ExecMode::Webdav => config.to_remote_path(exe.as_ref())?,
+        ExecMode::Upload => {
+            upload::upload_to_cache(config, target_os, exe.as_ref().as_ref()).await?
+        }
+    };
 
     let args_vec: Vec<String> = args
         .map(|arg| {
-            arg.as_ref()
+            let arg = arg
+                .as_ref()
                 .to_str()
-                .ok_or_else(|| anyhow::anyhow!("Arg is not valid UTF-8"))
-                .map(String::from)
+                .ok_or_else(|| anyhow::anyhow!("Arg is not valid UTF-8"))?;
+            match path_args::split_path_arg(&config.path_args, arg) {
+                Some((prefix, path)) => Ok(format!(
+                    "{}{}",
+                    prefix,
+                    config.to_remote_path(OsStr::new(path))?
+                )),
+                None => Ok(arg.to_string()),
+            }
         })
-        .collect::<Result<_, _>>()?;
-
-    let webdav_path = format!(
-        "\\\\localhost@{}\\DavWWWRoot",
-        ssh_remote_fs_server_port
-    );
+        .collect::<anyhow::Result<_>>()?;
 
     let ctx = ExecContext {
         cwd: remote_cwd,
         envs,
         bin_path,
         args: args_vec,
-        webdav_path,
-    };
-    let encoded = encode_context(&ctx);
-
-    let remote_bin = get_remote_bin_path(target, ssh_remote_fs_server_port)?;
-
-    let mut command = Command::new("ssh");
-    command
-        .arg("-S")
-        .arg(ssh_ctrl_path)
-        .args(["-o", "PreferredAuthentications=none"])
-        .arg(ssh_destination)
-        .arg(&remote_bin)
-        .arg(&encoded);
-
-    let status = command.status().await?;
-    Ok((status.code().unwrap_or(1) as u8).into())
-}
-
-fn get_remote_bin_path(target: &str, port: u16) -> anyhow::Result<String> {
-    let bin_name = if target.contains("windows") {
-        "cargo-xrun-remote-i686-pc-windows-gnullvm.exe"
-    } else if target.contains("x86_64") && target.contains("linux") {
-        "cargo-xrun-remote-x86_64-unknown-linux-musl"
-    } else if target.contains("aarch64") && target.contains("linux") {
-        "cargo-xrun-remote-aarch64-unknown-linux-musl"
-    } else {
-        anyhow::bail!("Unsupported target: {}", target)
+        webdav_path: config.webdav_path(),
+        clear_env: config.env.forward.hermetic,
+        session: String::new(),
+        report_url: String::new(),
+        argv0: launch.argv0.clone(),
+        env_remove: launch.env_remove.clone(),
+        umask: launch.umask,
+        stdin: config.remote_stdin(&launch.stdin)?,
+        timeout_secs: launch.timeout_secs,
     };
-    Ok(format!(
-        "\\\\localhost@{}\\DavWWWRoot\\remote-bin\\{}",
-        port, bin_name
-    ))
+    let execution = config.exec(ctx, tty, false).await?;
+    match execution.outcome {
+        Some(outcome) => {
+            if let Some(reason) = outcome::describe(outcome) {
+                eprintln!("cargo-xrun: remote process {}", reason);
+            }
+            Ok(outcome::exit_code(outcome).into())
+        }
+        None if execution.ssh_failed() => anyhow::bail!(
+            "ssh session to {} failed before the remote process reported its exit status",
+            config.ssh_destination
+        ),
+        // The agent itself failed before starting the executable, and said why on stderr.
+        None => Ok((execution.output.status.code().unwrap_or(1) as u8).into()),
+    }
 }
@@ -0,0 +1,126 @@
+use cargo_xrun_remote::outcome::Outcome;
+
+/// Names of the Linux signals a process is commonly killed by.
+fn signal_name(signal: i32) -> Option<&'static str> {
+    Some(match signal {
+        1 => "SIGHUP",
+        2 => "SIGINT",
+        3 => "SIGQUIT",
+        4 => "SIGILL",
+        5 => "SIGTRAP",
+        6 => "SIGABRT",
+        7 => "SIGBUS",
+        8 => "SIGFPE",
+        9 => "SIGKILL",
+        10 => "SIGUSR1",
+        11 => "SIGSEGV",
+        12 => "SIGUSR2",
+        13 => "SIGPIPE",
+        14 => "SIGALRM",
+        15 => "SIGTERM",
+        24 => "SIGXCPU",
+        25 => "SIGXFSZ",
+        31 => "SIGSYS",
+        _ => return None,
+    })
+}
+
+/// Description of a Windows exception code, and the signal a Unix process would have died of.
+fn exception_info(code: u32) -> Option<(&'static str, i32)> {
+    Some(match code {
+        0xC000_0005 => ("access violation", 11),
+        0xC000_001D => ("illegal instruction", 4),
+        0xC000_0094 => ("integer division by zero", 8),
+        0xC000_0095 => ("integer overflow", 8),
+        0xC000_00FD => ("stack overflow", 11),
+        0xC000_013A => ("terminated by Ctrl+C", 2),
+        0xC000_0409 => ("fast fail, e.g. an abort", 6),
+        _ => return None,
+    })
+}
+
+/// Readable reason for an outcome that is not a plain exit code, such as "killed by SIGSEGV".
+pub fn describe(outcome: Outcome) -> Option<String> {
+    match outcome {
+        Outcome::Exited(code) if (0..=255).contains(&code) => None,
+        Outcome::Exited(code) => Some(format!("exited with code {}", code)),
+        Outcome::Signaled(signal) => Some(match signal_name(signal) {
+            Some(name) => format!("killed by {}", name),
+            None => format!("killed by signal {}", signal),
+        }),
+        Outcome::Exception(code) => Some(match exception_info(code) {
+            Some((description, _)) => format!("{} (exception {:#010X})", description, code),
+            None => format!("unhandled exception {:#010X}", code),
+        }),
+        Outcome::TimedOut(secs) => Some(format!("timed out after {}s and was killed", secs)),
+    }
+}
+
+/// Exit code cargo-xrun reports for an outcome. Abnormal terminations follow the shell convention
+/// of 128 + signal on every target, with Windows exceptions mapped to the matching signal. Timeouts
+/// exit with 124, like timeout(1).
+pub fn exit_code(outcome: Outcome) -> u8 {
+    const SIGABRT: i32 = 6;
+    match outcome {
+        Outcome::Exited(code) => u8::try_from(code).unwrap_or(1),
+        Outcome::Signaled(signal) => (128 + signal.clamp(1, 127)) as u8,
+        Outcome::Exception(code) => {
+            let signal = exception_info(code).map_or(SIGABRT, |(_, signal)| signal);
+            (128 + signal) as u8
+        }
+        Outcome::TimedOut(_) => 124,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outcome_roundtrip() {
+        for outcome in [
+            Outcome::Exited(3),
+            Outcome::Exited(-1),
+            Outcome::Signaled(11),
+            Outcome::Exception(0xC000_0005),
+            Outcome::TimedOut(30),
+        ] {
+            assert_eq!(Outcome::decode(&outcome.encode()), Some(outcome));
+        }
+        assert_eq!(
+            Outcome::decode("exception c0000005\n"),
+            Some(Outcome::Exception(0xC000_0005))
+        );
+        assert_eq!(Outcome::decode("crashed"), None);
+    }
+
+    #[test]
+    fn test_describe_and_exit_code() {
+        assert_eq!(describe(Outcome::Exited(3)), None);
+        assert_eq!(exit_code(Outcome::Exited(3)), 3);
+        assert_eq!(
+            describe(Outcome::Exited(300)).unwrap(),
+            "exited with code 300"
+        );
+        assert_eq!(exit_code(Outcome::Exited(300)), 1);
+
+        assert_eq!(
+            describe(Outcome::Signaled(11)).unwrap(),
+            "killed by SIGSEGV"
+        );
+        assert_eq!(exit_code(Outcome::Signaled(11)), 139);
+
+        assert_eq!(
+            describe(Outcome::Exception(0xC000_0005)).unwrap(),
+            "access violation (exception 0xC0000005)"
+        );
+        assert_eq!(exit_code(Outcome::Exception(0xC000_0005)), 139);
+        assert_eq!(exit_code(Outcome::Exception(0xC000_0135)), 134);
+
+        assert_eq!(
+            describe(Outcome::TimedOut(30)).unwrap(),
+            "timed out after 30s and was killed"
+        );
+        assert_eq!(exit_code(Outcome::TimedOut(30)), 124);
+    }
+}
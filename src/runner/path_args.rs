@@ -0,0 +1,60 @@
+use std::path::{MAIN_SEPARATOR, Path};
+
+use crate::{config::PathArgs, pattern::glob_match};
+
+/// Finds the host path in a target argument, if `path_args` says it has one.
+///
+/// Returns the text before the path, i.e. `--name=` for `--name=value` arguments or an empty
+/// string, together with the path itself.
+pub fn split_path_arg<'a>(path_args: &PathArgs, arg: &'a str) -> Option<(&'a str, &'a str)> {
+    let (prefix, value) = option_value(arg).unwrap_or(("", arg));
+    let is_path = !value.is_empty()
+        && (path_args
+            .patterns
+            .iter()
+            .any(|pattern| glob_match(pattern, value))
+            || (path_args.detect_existing && looks_like_path(value) && Path::new(value).exists()));
+    is_path.then_some((prefix, value))
+}
+
+/// Splits `--name=value` into `("--name=", "value")`.
+fn option_value(arg: &str) -> Option<(&str, &str)> {
+    if !arg.starts_with('-') {
+        return None;
+    }
+    let index = arg.find('=')?;
+    Some(arg.split_at(index + 1))
+}
+
+/// Plain words such as test name filters are never treated as paths, even when a file of that
+/// name happens to exist.
+fn looks_like_path(value: &str) -> bool {
+    value.contains('/') || value.contains(MAIN_SEPARATOR) || Path::new(value).is_absolute()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_path_arg() {
+        let path_args = PathArgs {
+            detect_existing: true,
+            patterns: vec!["*.toml".into()],
+        };
+
+        assert_eq!(
+            split_path_arg(&path_args, "out/a.toml"),
+            Some(("", "out/a.toml"))
+        );
+        assert_eq!(
+            split_path_arg(&path_args, "--config=out/a.toml"),
+            Some(("--config=", "out/a.toml"))
+        );
+        assert_eq!(split_path_arg(&path_args, "./src"), Some(("", "./src")));
+        // Existing, but not path-like
+        assert_eq!(split_path_arg(&path_args, "src"), None);
+        assert_eq!(split_path_arg(&path_args, "--nocapture"), None);
+        assert_eq!(split_path_arg(&PathArgs::default(), "./src"), None);
+    }
+}
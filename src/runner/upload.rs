@@ -0,0 +1,94 @@
+use std::{path::Path, process::Stdio};
+
+use anyhow::Context as _;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncReadExt as _;
+
+use super::RunnerConfig;
+use crate::target::TargetOs;
+
+/// Makes `exe` available in the agent's content-addressed cache on the remote and returns the
+/// remote path of the cached copy. The upload is skipped when the remote already has an entry for
+/// the executable's hash.
+pub async fn upload_to_cache(
+    config: &RunnerConfig,
+    target_os: TargetOs,
+    exe: &Path,
+) -> anyhow::Result<String> {
+    let file_name = exe
+        .file_name()
+        .and_then(|name| name.to_str())
+        .with_context(|| format!("Invalid executable file name: {:?}", exe))?;
+    let (hash, size) = hash_file(exe)
+        .await
+        .with_context(|| format!("Failed to hash {:?}", exe))?;
+
+    let lookup = config
+        .ssh_command(false)
+        .arg(target_os.quote_arg(&config.remote_agent_path))
+        .args(["cache-lookup", &hash, &target_os.quote_arg(file_name)])
+        .stderr(Stdio::inherit())
+        .output()
+        .await
+        .context("Failed to spawn ssh for cache lookup")?;
+    match lookup.status.code() {
+        Some(0) => return remote_path_from_stdout(lookup.stdout),
+        Some(2) => {}
+        _ => anyhow::bail!("Remote cache lookup failed (status {:?})", lookup.status),
+    }
+
+    let mut child = config
+        .ssh_command(false)
+        .arg(target_os.quote_arg(&config.remote_agent_path))
+        .args([
+            "cache-put",
+            &hash,
+            &target_os.quote_arg(file_name),
+            &size.to_string(),
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("Failed to spawn ssh for upload")?;
+    let mut stdin = child.stdin.take().unwrap();
+    let mut file = tokio::fs::File::open(exe).await?;
+    tokio::io::copy(&mut file, &mut stdin)
+        .await
+        .with_context(|| format!("Failed to upload {:?}", exe))?;
+    drop(stdin);
+
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        anyhow::bail!("Upload of {:?} failed (status {:?})", exe, output.status);
+    }
+    remote_path_from_stdout(output.stdout)
+}
+
+async fn hash_file(path: &Path) -> std::io::Result<(String, u64)> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+    let hash = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    Ok((hash, size))
+}
+
+fn remote_path_from_stdout(stdout: Vec<u8>) -> anyhow::Result<String> {
+    String::from_utf8(stdout)
+        .ok()
+        .map(|path| path.trim().to_string())
+        .filter(|path| !path.is_empty())
+        .context("Remote agent did not report the cached path")
+}
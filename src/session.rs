@@ -0,0 +1,484 @@
+//! Sessions with a target's host for running commands outside of cargo, such as from an xtask or
+//! a test harness.
+
+use std::{ffi::OsStr, path::PathBuf, time::Duration};
+
+use cargo_xrun_remote::{ExecContext, protocol};
+use tokio::task::JoinHandle;
+
+use crate::{
+    agent::RemoteAgent,
+    config::{self, Host, TargetConfig},
+    embedded_binaries, fs_server, pool,
+    runner::{self, LaunchOptions, RunnerConfig, StdinSource},
+    ssh_master::SshMaster,
+    target::{self, TargetOs, TargetSpec},
+};
+
+pub use cargo_xrun_remote::outcome::Outcome;
+
+/// Settings for [`RemoteSession::start`].
+#[derive(Debug, Clone, Default)]
+pub struct SessionOptions {
+    /// Connect to this host instead of the hosts the target is mapped to, as with
+    /// `cargo xrun --host`: the alias or destination of a configured host, or any SSH destination.
+    pub host: Option<String>,
+    /// Host directories the remote may read, in addition to the target's `exported_paths` from
+    /// the config file. Host paths in commands must lie inside one of them.
+    pub exported_paths: Vec<PathBuf>,
+    /// Host directories the remote may write to as well. Must be empty for targets other than
+    /// Windows.
+    pub writable_dirs: Vec<PathBuf>,
+}
+
+/// A connection to a host of a target, along with the file server that makes host paths
+/// available on it.
+///
+/// ```no_run
+/// use cargo_xrun::{RemoteCommand, RemoteSession, SessionOptions};
+///
+/// # async fn example() -> anyhow::Result<()> {
+/// let workspace = std::env::current_dir()?;
+/// let session = RemoteSession::start(
+///     "aarch64-unknown-linux-gnu",
+///     SessionOptions {
+///         exported_paths: vec![workspace.clone()],
+///         ..Default::default()
+///     },
+/// )
+/// .await?;
+///
+/// let app = workspace.join("target/aarch64-unknown-linux-gnu/debug/app");
+/// let mut command = RemoteCommand::new(app);
+/// command.arg("--input").path_arg(workspace.join("fixtures/input.txt"));
+/// let output = session.output(&command).await?;
+/// assert!(output.success(), "{}", String::from_utf8_lossy(&output.stderr));
+///
+/// session.shutdown().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct RemoteSession {
+    host: Host,
+    ssh_master: SshMaster,
+    _server: ServerTask,
+    runner_config: RunnerConfig,
+}
+
+/// Stops the file server with the session.
+struct ServerTask(JoinHandle<anyhow::Error>);
+
+impl Drop for ServerTask {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+impl RemoteSession {
+    /// Starts the file server, connects to a host of `target` as set up in the config file and
+    /// installs the agent there. `target` is a target triple or the path of a target spec file.
+    pub async fn start(target: &str, options: SessionOptions) -> anyhow::Result<Self> {
+        let target_spec = TargetSpec::resolve(target).await?;
+        let target_config =
+            config::load_for_target(target::short_name(target), options.host.as_deref())?;
+        let writable_dirs = options
+            .writable_dirs
+            .into_iter()
+            .map(std::path::absolute)
+            .collect::<Result<Vec<_>, _>>()?;
+        let exported_roots = target_config
+            .exported_paths
+            .iter()
+            .cloned()
+            .chain(options.exported_paths)
+            .chain(writable_dirs.iter().cloned())
+            .map(std::path::absolute)
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::open(
+            &target_spec,
+            &target_config,
+            exported_roots,
+            writable_dirs,
+            protocol::CAP_OUTCOME | protocol::CAP_CLEAR_ENV | protocol::CAP_LAUNCH_OPTIONS,
+        )
+        .await
+    }
+
+    /// Sets up a session for `cli_main` and [`Self::start`], checking that an agent from the
+    /// config file has `capabilities`.
+    pub(crate) async fn open(
+        target_spec: &TargetSpec,
+        target_config: &TargetConfig,
+        exported_roots: Vec<PathBuf>,
+        writable_dirs: Vec<PathBuf>,
+        capabilities: u32,
+    ) -> anyhow::Result<Self> {
+        let target_os = target_spec.target_os()?;
+        anyhow::ensure!(
+            writable_dirs.is_empty() || target_os == TargetOs::Windows,
+            "Writable directories (--allow-writes, --writable-dir) are only supported for Windows \
+            targets: on Linux, remote processes work on a copy of the host files that is not \
+            written back"
+        );
+        let agent = embedded_binaries::for_target(target_spec, target_config.agent.as_deref())?;
+
+        let fs_server_token = fs_server::generate_token();
+        let (dav_port, server_fut) = fs_server::serve_webdav(
+            &fs_server_token,
+            exported_roots.clone(),
+            writable_dirs,
+            Some(&agent),
+        )
+        .await?;
+        let server = ServerTask(tokio::spawn(server_fut));
+
+        let (host, ssh_master) = pool::connect(
+            &target_config.hosts,
+            target_config.pool_strategy,
+            dav_port,
+            target_os,
+        )
+        .await?;
+
+        let remote_agent =
+            RemoteAgent::prepare(&ssh_master, &agent, target_os, host.remote_dir.as_deref())
+                .await?;
+        // Embedded agents are installed by hash, so they always match; an agent from the config
+        // file may have been built from another version of cargo-xrun.
+        if target_config.agent.is_some() {
+            remote_agent
+                .check_version(&ssh_master, target_os, capabilities)
+                .await?;
+        }
+        let session_id = fs_server::generate_token();
+        remote_agent
+            .open_session(&ssh_master, target_os, &session_id, &fs_server_token)
+            .await?;
+
+        let runner_config = RunnerConfig {
+            target_os,
+            ssh_ctrl_path: ssh_master.control_path().to_path_buf(),
+            ssh_destination: host.destination.clone(),
+            fs_server_port: dav_port,
+            remote_fs_server_port: ssh_master.remote_port(),
+            fs_server_token,
+            session_id,
+            remote_agent_path: remote_agent.path().to_string(),
+            exec_mode: runner::ExecMode::Webdav,
+            exported_roots,
+            path_args: Default::default(),
+            env: Default::default(),
+            tty: runner::TtyMode::Never,
+            launch: LaunchOptions::default(),
+        };
+        Ok(Self {
+            host,
+            ssh_master,
+            _server: server,
+            runner_config,
+        })
+    }
+
+    /// The host the session is connected to, as configured.
+    pub(crate) fn host(&self) -> &Host {
+        &self.host
+    }
+
+    /// Runner settings for the session, with the defaults of `cargo xrun` for everything that is
+    /// not about the session itself.
+    pub(crate) fn runner_config(&self) -> &RunnerConfig {
+        &self.runner_config
+    }
+
+    /// Whether the connection to the host is still up.
+    pub async fn is_connected(&self) -> bool {
+        self.ssh_master.is_connected().await
+    }
+
+    /// The alias of the host the session is connected to, or its SSH destination.
+    pub fn host_name(&self) -> &str {
+        self.ssh_master.name()
+    }
+
+    /// Path on the remote of the host path `path`, which must lie in an exported directory.
+    pub fn remote_path(&self, path: impl AsRef<OsStr>) -> anyhow::Result<String> {
+        self.runner_config.to_remote_path(path.as_ref())
+    }
+
+    /// Runs `command` with stdout and stderr inherited, and returns how it ended.
+    pub async fn status(&self, command: &RemoteCommand) -> anyhow::Result<Outcome> {
+        let execution = self
+            .runner_config
+            .exec(self.context(command)?, false, false)
+            .await?;
+        self.outcome(&execution)
+    }
+
+    /// Runs `command` with stdout and stderr captured.
+    pub async fn output(&self, command: &RemoteCommand) -> anyhow::Result<RemoteOutput> {
+        let execution = self
+            .runner_config
+            .exec(self.context(command)?, false, true)
+            .await?;
+        let status = self.outcome(&execution)?;
+        Ok(RemoteOutput {
+            status,
+            stdout: execution.output.stdout,
+            stderr: execution.output.stderr,
+        })
+    }
+
+    /// Closes the connection and stops the file server. Dropping the session does the same
+    /// without waiting for ssh to exit.
+    pub async fn shutdown(self) -> anyhow::Result<()> {
+        // The agent also forgets sessions that are never closed, after a while.
+        let config = &self.runner_config;
+        let _ = self
+            .ssh_master
+            .command()
+            .arg(config.target_os.quote_arg(&config.remote_agent_path))
+            .args(["session-close", &config.session_id])
+            .stdin(std::process::Stdio::null())
+            .output()
+            .await;
+        self.ssh_master.stop().await?;
+        Ok(())
+    }
+
+    fn context(&self, command: &RemoteCommand) -> anyhow::Result<ExecContext> {
+        let config = &self.runner_config;
+        let remote_arg = |arg: &Arg| match arg {
+            Arg::Plain(arg) => Ok(arg.clone()),
+            Arg::HostPath(path) => config.to_remote_path(path.as_os_str()),
+        };
+        let launch = &command.launch;
+        Ok(ExecContext {
+            // The agent starts out in the remote user's home directory.
+            cwd: match &command.current_dir {
+                Some(dir) => config.to_remote_path(dir.as_os_str())?,
+                None => ".".to_string(),
+            },
+            envs: command.envs.clone(),
+            bin_path: remote_arg(&command.program)?,
+            args: command
+                .args
+                .iter()
+                .map(remote_arg)
+                .collect::<anyhow::Result<_>>()?,
+            webdav_path: config.webdav_path(),
+            clear_env: command.env_clear,
+            session: String::new(),
+            report_url: String::new(),
+            argv0: launch.argv0.clone(),
+            env_remove: launch.env_remove.clone(),
+            umask: launch.umask,
+            stdin: config.remote_stdin(&launch.stdin)?,
+            timeout_secs: launch.timeout_secs,
+        })
+    }
+
+    fn outcome(&self, execution: &runner::Execution) -> anyhow::Result<Outcome> {
+        match execution.outcome {
+            Some(outcome) => Ok(outcome),
+            None if execution.ssh_failed() => anyhow::bail!(
+                "ssh session to {} failed before the remote process reported its exit status",
+                self.host_name()
+            ),
+            None => anyhow::bail!(
+                "The agent on {} failed before starting the command ({}) {}",
+                self.host_name(),
+                execution.output.status,
+                String::from_utf8_lossy(&execution.output.stderr).trim()
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Arg {
+    Plain(String),
+    /// Mapped to the matching remote path when the command runs.
+    HostPath(PathBuf),
+}
+
+/// A command for [`RemoteSession`] to run, built like [`std::process::Command`].
+///
+/// Unless set otherwise, the command runs in the remote user's home directory, on top of the
+/// remote user's environment, with stdin closed.
+#[derive(Debug, Clone)]
+pub struct RemoteCommand {
+    program: Arg,
+    args: Vec<Arg>,
+    envs: Vec<(String, String)>,
+    env_clear: bool,
+    current_dir: Option<PathBuf>,
+    launch: LaunchOptions,
+}
+
+impl RemoteCommand {
+    /// Runs the host executable at `program`, which must lie in an exported directory.
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        Self::with_program(Arg::HostPath(program.into()))
+    }
+
+    /// Runs `program` from the remote's `PATH`, or at a path on the remote.
+    pub fn remote(program: impl Into<String>) -> Self {
+        Self::with_program(Arg::Plain(program.into()))
+    }
+
+    fn with_program(program: Arg) -> Self {
+        Self {
+            program,
+            args: Vec::new(),
+            envs: Vec::new(),
+            env_clear: false,
+            current_dir: None,
+            launch: LaunchOptions {
+                stdin: StdinSource::Null,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Adds an argument, passed as is.
+    pub fn arg(&mut self, arg: impl Into<String>) -> &mut Self {
+        self.args.push(Arg::Plain(arg.into()));
+        self
+    }
+
+    pub fn args<I: IntoIterator<Item = S>, S: Into<String>>(&mut self, args: I) -> &mut Self {
+        for arg in args {
+            self.arg(arg);
+        }
+        self
+    }
+
+    /// Adds the remote path of the host path `path`, which must lie in an exported directory.
+    pub fn path_arg(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.args.push(Arg::HostPath(path.into()));
+        self
+    }
+
+    pub fn env(&mut self, key: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        self.envs.push((key.into(), value.into()));
+        self
+    }
+
+    /// Removes `key` from the environment the command inherits on the remote.
+    pub fn env_remove(&mut self, key: impl Into<String>) -> &mut Self {
+        self.launch.env_remove.push(key.into());
+        self
+    }
+
+    /// Starts the command with only the variables set with [`Self::env`].
+    pub fn env_clear(&mut self) -> &mut Self {
+        self.env_clear = true;
+        self
+    }
+
+    /// Runs the command in the host directory `dir`, which must lie in an exported directory.
+    pub fn current_dir(&mut self, dir: impl Into<PathBuf>) -> &mut Self {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    /// Starts the command with `arg0` as `argv[0]`. Ignored on Windows targets.
+    pub fn arg0(&mut self, arg0: impl Into<String>) -> &mut Self {
+        self.launch.argv0 = Some(arg0.into());
+        self
+    }
+
+    /// Starts the command with the file mode creation mask `umask`. Ignored on Windows targets.
+    pub fn umask(&mut self, umask: u32) -> &mut Self {
+        self.launch.umask = Some(umask);
+        self
+    }
+
+    /// Feeds the command the host file at `path`, which must lie in an exported directory, as
+    /// stdin.
+    pub fn stdin_file(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.launch.stdin = StdinSource::File(path.into());
+        self
+    }
+
+    /// Kills the command once it has run for `timeout`, rounded up to whole seconds, which ends
+    /// it with [`Outcome::TimedOut`].
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        let secs = timeout.as_secs() + u64::from(timeout.subsec_nanos() > 0);
+        self.launch.timeout_secs = Some(secs);
+        self
+    }
+}
+
+/// Output of a command run with [`RemoteSession::output`].
+#[derive(Debug, Clone)]
+pub struct RemoteOutput {
+    pub status: Outcome,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl RemoteOutput {
+    /// Whether the command exited with code 0.
+    pub fn success(&self) -> bool {
+        self.status == Outcome::Exited(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remote_command() {
+        let mut command = RemoteCommand::new("/work/target/debug/app");
+        command
+            .arg("--input")
+            .path_arg("/work/fixtures/in.txt")
+            .env("RUST_LOG", "debug")
+            .timeout(Duration::from_millis(1500));
+        assert!(
+            matches!(&command.program, Arg::HostPath(path) if path == "/work/target/debug/app")
+        );
+        assert!(
+            matches!(&command.args[..], [Arg::Plain(flag), Arg::HostPath(path)]
+            if flag == "--input" && path == "/work/fixtures/in.txt")
+        );
+        assert_eq!(command.launch.timeout_secs, Some(2));
+        assert_eq!(command.launch.stdin, StdinSource::Null);
+        assert!(command.launch.required_capabilities() != 0);
+    }
+
+    #[tokio::test]
+    async fn test_open_rejects_writable_dirs_on_linux() {
+        let target_spec = TargetSpec {
+            arch: "aarch64".into(),
+            os: "linux".into(),
+            env: "gnu".into(),
+        };
+        let target_config = TargetConfig {
+            hosts: Vec::new(),
+            pool_strategy: Default::default(),
+            exported_paths: Vec::new(),
+            path_args: Default::default(),
+            env_forward: Default::default(),
+            agent: None,
+        };
+        let err = RemoteSession::open(
+            &target_spec,
+            &target_config,
+            vec![PathBuf::from("/work")],
+            vec![PathBuf::from("/work/target")],
+            0,
+        )
+        .await
+        .err()
+        .unwrap();
+        assert!(
+            err.to_string()
+                .starts_with("Writable directories (--allow-writes, --writable-dir) are only"),
+            "{}",
+            err
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use std::{
+    ffi::OsString,
     mem::ManuallyDrop,
     path::{Path, PathBuf},
     process::{ExitStatus, Stdio},
@@ -12,8 +13,31 @@ use tokio::{
     process::Command,
 };
 
+use crate::{config::Host, target::TargetOs};
+
+/// `ssh` options that connect to `host` as configured, on top of `~/.ssh/config`.
+pub fn connection_args(host: &Host) -> Vec<OsString> {
+    let mut args: Vec<OsString> = Vec::new();
+    if let Some(port) = host.port {
+        args.extend(["-p".into(), port.to_string().into()]);
+    }
+    if let Some(identity_file) = &host.identity_file {
+        args.extend(["-i".into(), identity_file.into()]);
+    }
+    if !host.proxy_jump.is_empty() {
+        args.extend(["-J".into(), host.proxy_jump.join(",").into()]);
+    }
+    for option in &host.ssh_options {
+        args.extend(["-o".into(), option.into()]);
+    }
+    args
+}
+
 pub struct SshMaster {
     control_path: NamedTempFile<PathBuf>,
+    destination: String,
+    /// The host's alias, or its destination.
+    name: String,
     master_daemon: InterruptibleChild,
     remote_port: u16,
 }
@@ -25,10 +49,46 @@ impl SshMaster {
     pub fn remote_port(&self) -> u16 {
         self.remote_port
     }
-    pub async fn start(ssh_destination: &str, forward_port: u16) -> anyhow::Result<Self> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Creates an `ssh` command that opens a new session over the master connection.
+    /// The remote command and its arguments are left for the caller to append.
+    pub fn command(&self) -> Command {
+        let mut command = Command::new("ssh");
+        command
+            .arg("-S")
+            .arg(self.control_path())
+            .args(["-o", "PreferredAuthentications=none"])
+            .arg(&self.destination);
+        command
+    }
+
+    /// Whether the master connection is still up.
+    pub async fn is_connected(&self) -> bool {
+        Command::new("ssh")
+            .arg("-S")
+            .arg(self.control_path())
+            .args(["-O", "check"])
+            .arg(&self.destination)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await
+            .is_ok_and(|status| status.success())
+    }
+
+    /// Connects to `host` and forwards a port on the remote to `forward_port` on the host.
+    /// Sessions opened with [`Self::command`] reuse this connection and its settings.
+    pub async fn start(host: &Host, forward_port: u16, os: TargetOs) -> anyhow::Result<Self> {
+        let ssh_destination = &host.destination;
         let control_path = tempfile::Builder::new().make(|path: &Path| Ok(path.to_path_buf()))?;
 
-        let mut master_daemon = Command::new("ssh")
+        let mut command = Command::new("ssh");
+        command
+            .args(connection_args(host))
             .args([
                 "-R",
                 &format!("0:localhost:{}", forward_port), // remote port forwarding
@@ -37,9 +97,21 @@ impl SshMaster {
                 "-M", // master mode
                 "-S", // socket path
             ])
-            .arg(control_path.as_file().as_os_str())
-            .arg(ssh_destination)
-            .arg("sc start WebClient >nul 2>nul & pause >nul 2>nul")
+            .arg(control_path.as_file().as_os_str());
+        match os {
+            // The WebClient service backs the `\\localhost@PORT\DavWWWRoot` paths used by the agent.
+            TargetOs::Windows => {
+                command
+                    .arg(ssh_destination)
+                    .arg("sc start WebClient >nul 2>nul & pause >nul 2>nul");
+            }
+            // No remote command is needed; the master only holds the connection and the forwarding.
+            TargetOs::Linux => {
+                command.arg("-N").arg(ssh_destination);
+            }
+        }
+
+        let mut master_daemon = command
             // Must explicitly configure stdin to prevent inheriting a piped stdin from the parent.
             // If SSH inherits piped stdin, the Windows `pause` command fails with
             // "Input redirection is not supported", causing the SSH session to exit immediately
@@ -62,8 +134,8 @@ impl SshMaster {
                     break None;
                 }
                 let line = &line_buf[..n];
-                if line.starts_with(ALLOCATED_PORT_PREFIX) {
-                    let port_str = line[ALLOCATED_PORT_PREFIX.len()..]
+                if let Some(rest) = line.strip_prefix(ALLOCATED_PORT_PREFIX) {
+                    let port_str = rest
                         .split_whitespace()
                         .next()
                         .context("Failed to parse allocated port from ssh output")?;
@@ -78,13 +150,16 @@ impl SshMaster {
         let Some(remote_port) = remote_port else {
             let status = master_daemon.wait().await?;
             anyhow::bail!(
-                "ssh exited without allocating a remote port (status {:?})",
+                "ssh to {} exited without allocating a remote port (status {:?})",
+                host.name(),
                 status
             );
         };
 
         Ok(Self {
             control_path,
+            destination: ssh_destination.clone(),
+            name: host.name().to_string(),
             master_daemon,
             remote_port,
         })
@@ -104,3 +179,34 @@ impl Drop for SshMaster {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_args() {
+        let host = Host {
+            destination: "user@server.com".into(),
+            port: Some(2222),
+            identity_file: Some("/home/user/.ssh/ci".into()),
+            proxy_jump: vec!["bastion1".into(), "user@bastion2:2200".into()],
+            ssh_options: vec!["ServerAliveInterval=30".into()],
+            ..Default::default()
+        };
+        assert_eq!(
+            connection_args(&host),
+            [
+                "-p",
+                "2222",
+                "-i",
+                "/home/user/.ssh/ci",
+                "-J",
+                "bastion1,user@bastion2:2200",
+                "-o",
+                "ServerAliveInterval=30"
+            ]
+        );
+        assert!(connection_args(&Host::default()).is_empty());
+    }
+}
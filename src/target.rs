@@ -0,0 +1,243 @@
+use std::{env, path::Path};
+
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+/// The `target_arch`, `target_os` and `target_env` cfg values of a target, which decide the agent
+/// and how the remote side is driven.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetSpec {
+    pub arch: String,
+    pub os: String,
+    pub env: String,
+}
+
+impl TargetSpec {
+    /// Resolves `target`, a target triple or the path of a custom target JSON file as accepted by
+    /// `--target`. Triples are resolved by rustc, so any triple it knows works.
+    pub async fn resolve(target: &str) -> anyhow::Result<Self> {
+        if target.ends_with(".json") {
+            let json = std::fs::read_to_string(target)
+                .with_context(|| format!("Failed to read target specification {}", target))?;
+            return Self::from_json(&json)
+                .with_context(|| format!("Invalid target specification {}", target));
+        }
+
+        let rustc = env::var("RUSTC").unwrap_or("rustc".into());
+        let output = Command::new(&rustc)
+            .args(["--print", "cfg", "--target", target])
+            .output()
+            .await
+            .with_context(|| format!("Failed to run {} --print cfg", rustc))?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "{} does not know target {}:\n{}",
+                rustc,
+                target,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Self::from_cfg(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    /// Parses the output of `rustc --print cfg`, lines such as `target_os="linux"`.
+    fn from_cfg(cfg: &str) -> anyhow::Result<Self> {
+        let value = |name: &str| {
+            cfg.lines().find_map(|line| {
+                let value = line.strip_prefix(name)?.strip_prefix("=\"")?;
+                Some(value.strip_suffix('"')?.to_string())
+            })
+        };
+        Ok(Self {
+            arch: value("target_arch").context("rustc did not print target_arch")?,
+            os: value("target_os").context("rustc did not print target_os")?,
+            env: value("target_env").unwrap_or_default(),
+        })
+    }
+
+    /// Parses a custom target specification, which names the cfg values in its `arch`, `os` and
+    /// `env` fields.
+    fn from_json(json: &str) -> anyhow::Result<Self> {
+        #[derive(Deserialize)]
+        struct Spec {
+            arch: String,
+            // rustc's defaults for fields a specification leaves out.
+            #[serde(default = "default_os")]
+            os: String,
+            #[serde(default)]
+            env: String,
+        }
+        fn default_os() -> String {
+            "none".into()
+        }
+
+        let spec: Spec = serde_json::from_str(json)?;
+        Ok(Self {
+            arch: spec.arch,
+            os: spec.os,
+            env: spec.env,
+        })
+    }
+
+    pub fn target_os(&self) -> anyhow::Result<TargetOs> {
+        match self.os.as_str() {
+            "windows" => Ok(TargetOs::Windows),
+            "linux" => Ok(TargetOs::Linux),
+            os => anyhow::bail!(
+                "Unsupported target os {}: only Windows and Linux targets can run remotely",
+                os
+            ),
+        }
+    }
+}
+
+/// Name cargo uses for `target` in `target.<name>` config tables: the triple, or the file stem
+/// of a custom target JSON file.
+pub fn short_name(target: &str) -> &str {
+    if target.ends_with(".json") {
+        Path::new(target)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or(target)
+    } else {
+        target
+    }
+}
+
+/// Operating system family of a target, which decides how the remote side is driven.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetOs {
+    /// Executables run off the WebDAV share mounted by the Windows WebClient service.
+    Windows,
+    /// The agent is bootstrapped over SSH and fetches executables from the WebDAV server over HTTP.
+    Linux,
+}
+
+impl TargetOs {
+    /// Quotes `arg` for the shell sshd runs remote commands with: `cmd.exe` on Windows and a
+    /// POSIX shell on Linux. Arguments without special characters are returned unchanged.
+    pub fn quote_arg(self, arg: &str) -> String {
+        match self {
+            TargetOs::Windows => {
+                // Quoted as the C runtime splits command lines, then every character cmd.exe
+                // would interpret is escaped with `^`. Quotes are escaped too, so cmd.exe never
+                // sees a quoted section, in which `^` would stay literal but `%` would not.
+                let arg = if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || c == '"')
+                {
+                    quote_windows_arg(arg)
+                } else {
+                    arg.to_string()
+                };
+                let mut escaped = String::with_capacity(arg.len());
+                for c in arg.chars() {
+                    if "()%!^\"<>&|".contains(c) {
+                        escaped.push('^');
+                    }
+                    escaped.push(c);
+                }
+                escaped
+            }
+            TargetOs::Linux => {
+                if !arg.is_empty()
+                    && arg
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c))
+                {
+                    return arg.to_string();
+                }
+                format!("'{}'", arg.replace('\'', "'\\''"))
+            }
+        }
+    }
+}
+
+/// Quotes `arg` so that the C runtime of a Windows program reads it back as one argument:
+/// backslashes are only doubled where they precede a quote.
+fn quote_windows_arg(arg: &str) -> String {
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                quoted.extend(std::iter::repeat_n('\\', backslashes * 2 + 1));
+                backslashes = 0;
+            }
+            _ => {
+                quoted.extend(std::iter::repeat_n('\\', backslashes));
+                backslashes = 0;
+            }
+        }
+        if c != '\\' {
+            quoted.push(c);
+        }
+    }
+    quoted.extend(std::iter::repeat_n('\\', backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_spec() {
+        let cfg = "debug_assertions\ntarget_arch=\"arm\"\ntarget_endian=\"little\"\n\
+            target_env=\"gnu\"\ntarget_os=\"linux\"\ntarget_vendor=\"unknown\"\n";
+        let spec = TargetSpec::from_cfg(cfg).unwrap();
+        assert_eq!(
+            spec,
+            TargetSpec {
+                arch: "arm".into(),
+                os: "linux".into(),
+                env: "gnu".into(),
+            }
+        );
+        assert_eq!(spec.target_os().unwrap(), TargetOs::Linux);
+
+        let spec =
+            TargetSpec::from_json(r#"{"arch": "riscv64", "llvm-target": "riscv64"}"#).unwrap();
+        assert_eq!(spec.os, "none");
+        assert!(spec.target_os().is_err());
+        assert!(TargetSpec::from_json(r#"{"os": "linux"}"#).is_err());
+
+        assert_eq!(
+            short_name("x86_64-pc-windows-msvc"),
+            "x86_64-pc-windows-msvc"
+        );
+        assert_eq!(short_name("targets/my-board.json"), "my-board");
+    }
+
+    #[test]
+    fn test_quote_arg() {
+        assert_eq!(
+            TargetOs::Linux.quote_arg("/home/user/.cache/cargo-xrun/agent"),
+            "/home/user/.cache/cargo-xrun/agent"
+        );
+        assert_eq!(
+            TargetOs::Linux.quote_arg("/home/Jo Doe/it's"),
+            "'/home/Jo Doe/it'\\''s'"
+        );
+        assert_eq!(TargetOs::Linux.quote_arg("/home/jürgen"), "'/home/jürgen'");
+        assert_eq!(
+            TargetOs::Windows.quote_arg(r"C:\Users\jo\agent.exe"),
+            r"C:\Users\jo\agent.exe"
+        );
+        assert_eq!(
+            TargetOs::Windows.quote_arg(r"C:\Users\Jo Doe\agent.exe"),
+            r#"^"C:\Users\Jo Doe\agent.exe^""#
+        );
+        assert_eq!(TargetOs::Windows.quote_arg("%PATH%"), "^%PATH^%");
+        assert_eq!(
+            TargetOs::Windows.quote_arg(r#"say "hi" & exit"#),
+            r#"^"say \^"hi\^" ^& exit^""#
+        );
+        assert_eq!(
+            TargetOs::Windows.quote_arg(r"C:\Jo Doe\"),
+            r#"^"C:\Jo Doe\\^""#
+        );
+        assert_eq!(TargetOs::Windows.quote_arg(""), r#"^"^""#);
+    }
+}
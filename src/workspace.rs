@@ -0,0 +1,104 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context as _;
+use serde::Deserialize;
+use tokio::process::Command;
+
+/// The parts of `cargo metadata` cargo-xrun cares about.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Metadata {
+    pub workspace_root: PathBuf,
+    pub target_directory: PathBuf,
+    /// `[workspace.metadata]` of the workspace manifest.
+    #[serde(default)]
+    pub metadata: serde_json::Value,
+    pub packages: Vec<Package>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Package {
+    pub manifest_path: PathBuf,
+    /// `[package.metadata]` of the package manifest.
+    #[serde(default)]
+    pub metadata: serde_json::Value,
+}
+
+pub async fn metadata() -> anyhow::Result<Metadata> {
+    let cargo = env::var("CARGO").unwrap_or("cargo".into());
+    let output = Command::new(&cargo)
+        .args(["metadata", "--format-version", "1", "--no-deps"])
+        .output()
+        .await
+        .with_context(|| format!("Failed to run {} metadata", cargo))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "{} metadata failed (status {:?}):\n{}",
+            cargo,
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    serde_json::from_slice(&output.stdout).context("Failed to parse cargo metadata output")
+}
+
+async fn sysroot() -> anyhow::Result<PathBuf> {
+    let rustc = env::var("RUSTC").unwrap_or("rustc".into());
+    let output = Command::new(&rustc)
+        .args(["--print", "sysroot"])
+        .output()
+        .await
+        .with_context(|| format!("Failed to run {} --print sysroot", rustc))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "{} --print sysroot failed (status {:?})",
+            rustc,
+            output.status
+        );
+    }
+    let sysroot = String::from_utf8(output.stdout).context("Sysroot is not valid UTF-8")?;
+    Ok(PathBuf::from(sysroot.trim()))
+}
+
+fn cargo_home() -> Option<PathBuf> {
+    env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".cargo")))
+}
+
+/// Host directories the remote may read through the WebDAV server: the workspace, the target
+/// directory, `CARGO_HOME`, the toolchain sysroot and `extra_paths`.
+pub async fn exported_roots(
+    metadata: &Metadata,
+    extra_paths: impl IntoIterator<Item = PathBuf>,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let mut roots = vec![
+        metadata.workspace_root.clone(),
+        metadata.target_directory.clone(),
+        sysroot().await?,
+    ];
+    roots.extend(cargo_home());
+    for path in extra_paths {
+        roots.push(std::path::absolute(path)?);
+    }
+
+    // Paths are compared without resolving symlinks, so also accept the resolved form of each
+    // root. On Windows, canonical paths use the `\\?\` form, which never matches.
+    #[cfg(unix)]
+    for root in roots.clone() {
+        if let Ok(canonical) = root.canonicalize()
+            && canonical != root
+        {
+            roots.push(canonical);
+        }
+    }
+
+    Ok(roots)
+}
+
+/// Whether `path` lies inside one of `roots`.
+pub fn is_exported(path: &Path, roots: &[PathBuf]) -> bool {
+    roots.iter().any(|root| path.starts_with(root))
+}